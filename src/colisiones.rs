@@ -0,0 +1,196 @@
+// colisiones.rs
+// Choques entre vehículos usando su rectángulo real (largo y ancho del tipo) girado
+// según su rumbo. Primero una grilla descarta los pares que están lejos, y a los
+// que quedan se les aplica el teorema del eje separador: dos rectángulos no se tocan
+// si al proyectarlos sobre alguno de sus cuatro ejes las sombras no se solapan.
+use crate::modelo::Carro;
+use std::collections::HashMap;
+
+// Lado de las celdas de la grilla: más que el vehículo más largo, así cada uno cae
+// en pocas celdas
+pub const TAMANO_CELDA: f32 = 50.0;
+
+// Rectángulo orientado de un vehículo
+#[derive(Clone, Copy, Debug)]
+pub struct Rectangulo {
+    pub centro: [f32; 2],
+    pub ejes: [[f32; 2]; 2], // A lo largo y a lo ancho, normalizados
+    pub medios: [f32; 2],    // Medio largo y medio ancho
+}
+
+impl Rectangulo {
+    pub fn de(carro: &Carro) -> Self {
+        let [largo, ancho] = carro.tipo.dimensiones();
+        let (sin, cos) = carro.angulo.sin_cos();
+        Self {
+            centro: carro.posicion,
+            ejes: [[cos, sin], [-sin, cos]],
+            medios: [largo / 2.0, ancho / 2.0],
+        }
+    }
+
+    pub fn esquinas(&self) -> [[f32; 2]; 4] {
+        let [a, b] = self.ejes;
+        let [ma, mb] = self.medios;
+        let esquina = |sa: f32, sb: f32| [
+            self.centro[0] + a[0] * ma * sa + b[0] * mb * sb,
+            self.centro[1] + a[1] * ma * sa + b[1] * mb * sb,
+        ];
+        [esquina(1.0, 1.0), esquina(-1.0, 1.0), esquina(-1.0, -1.0), esquina(1.0, -1.0)]
+    }
+
+    // Si el punto está dentro del rectángulo, bordes incluidos
+    pub fn contiene(&self, punto: [f32; 2]) -> bool {
+        let d = [punto[0] - self.centro[0], punto[1] - self.centro[1]];
+        (0..2).all(|i| (d[0] * self.ejes[i][0] + d[1] * self.ejes[i][1]).abs() <= self.medios[i])
+    }
+
+    // Mitad de la sombra del rectángulo sobre un eje normalizado
+    fn media_proyeccion(&self, eje: [f32; 2]) -> f32 {
+        (0..2).map(|i| self.medios[i] * (self.ejes[i][0] * eje[0] + self.ejes[i][1] * eje[1]).abs()).sum()
+    }
+
+    // Si se solapan según el teorema del eje separador
+    pub fn toca(&self, otro: &Rectangulo) -> bool {
+        let d = [otro.centro[0] - self.centro[0], otro.centro[1] - self.centro[1]];
+        self.ejes.iter().chain(otro.ejes.iter()).all(|&eje| {
+            let distancia = (d[0] * eje[0] + d[1] * eje[1]).abs();
+            distancia <= self.media_proyeccion(eje) + otro.media_proyeccion(eje)
+        })
+    }
+
+    // Punto de contacto de dos rectángulos que se tocan: el promedio de las esquinas
+    // de cada uno que quedan dentro del otro. Si se cruzan sin que ninguna esquina
+    // quede adentro (en cruz), el punto medio entre los centros.
+    pub fn contacto(&self, otro: &Rectangulo) -> [f32; 2] {
+        let dentro: Vec<[f32; 2]> = self.esquinas().into_iter().filter(|&p| otro.contiene(p))
+            .chain(otro.esquinas().into_iter().filter(|&p| self.contiene(p)))
+            .collect();
+        if dentro.is_empty() {
+            return [(self.centro[0] + otro.centro[0]) / 2.0, (self.centro[1] + otro.centro[1]) / 2.0];
+        }
+        let n = dentro.len() as f32;
+        [dentro.iter().map(|p| p[0]).sum::<f32>() / n, dentro.iter().map(|p| p[1]).sum::<f32>() / n]
+    }
+
+    // Celdas de la grilla que cubre la caja alineada que lo contiene
+    fn celdas(&self) -> impl Iterator<Item = (i32, i32)> {
+        let esquinas = self.esquinas();
+        let minimo = |i: usize| esquinas.iter().map(|p| p[i]).fold(f32::INFINITY, f32::min);
+        let maximo = |i: usize| esquinas.iter().map(|p| p[i]).fold(f32::NEG_INFINITY, f32::max);
+        let celda = |v: f32| (v / TAMANO_CELDA).floor() as i32;
+        let (x0, x1) = (celda(minimo(0)), celda(maximo(0)));
+        let (y0, y1) = (celda(minimo(1)), celda(maximo(1)));
+        (x0..=x1).flat_map(move |x| (y0..=y1).map(move |y| (x, y)))
+    }
+}
+
+// Pares de rectángulos que se tocan, con su punto de contacto, ordenados por índice.
+// Solo se comparan los que comparten alguna celda de la grilla.
+pub fn detectar(rectangulos: &[Rectangulo]) -> Vec<(usize, usize, [f32; 2])> {
+    let mut grilla: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+    for (i, rectangulo) in rectangulos.iter().enumerate() {
+        for celda in rectangulo.celdas() {
+            grilla.entry(celda).or_default().push(i);
+        }
+    }
+
+    // Un par puede compartir varias celdas; se ordena para quitar repetidos y para
+    // que el resultado no dependa del orden del HashMap
+    let mut candidatos: Vec<(usize, usize)> = grilla.values()
+        .flat_map(|celda| {
+            celda.iter().enumerate()
+                .flat_map(move |(k, &i)| celda[k + 1..].iter().map(move |&j| (i.min(j), i.max(j))))
+        })
+        .collect();
+    candidatos.sort_unstable();
+    candidatos.dedup();
+
+    candidatos.into_iter()
+        .filter(|&(i, j)| rectangulos[i].toca(&rectangulos[j]))
+        .map(|(i, j)| (i, j, rectangulos[i].contacto(&rectangulos[j])))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+    fn rectangulo(centro: [f32; 2], angulo: f32, largo: f32, ancho: f32) -> Rectangulo {
+        let (sin, cos) = angulo.sin_cos();
+        Rectangulo { centro, ejes: [[cos, sin], [-sin, cos]], medios: [largo / 2.0, ancho / 2.0] }
+    }
+
+    fn cerca(a: [f32; 2], b: [f32; 2]) -> bool {
+        (a[0] - b[0]).abs() < 1e-3 && (a[1] - b[1]).abs() < 1e-3
+    }
+
+    #[test]
+    fn alineados_separados_no_se_tocan() {
+        let a = rectangulo([0.0, 0.0], 0.0, 20.0, 10.0);
+        assert!(!a.toca(&rectangulo([20.5, 0.0], 0.0, 20.0, 10.0)));
+        assert!(!a.toca(&rectangulo([0.0, 10.5], 0.0, 20.0, 10.0)));
+    }
+
+    #[test]
+    fn bordes_que_se_tocan_cuentan_como_choque() {
+        let a = rectangulo([0.0, 0.0], 0.0, 20.0, 10.0);
+        let b = rectangulo([20.0, 0.0], 0.0, 20.0, 10.0);
+        assert!(a.toca(&b) && b.toca(&a));
+        assert!(cerca(a.contacto(&b), [10.0, 0.0]));
+    }
+
+    #[test]
+    fn girado_con_cajas_alineadas_solapadas_no_toca() {
+        // Las cajas alineadas que los contienen se solapan, pero el eje diagonal del
+        // cuadrado girado los separa
+        let a = rectangulo([0.0, 0.0], 0.0, 20.0, 20.0);
+        let b = rectangulo([24.0, 24.0], FRAC_PI_4, 20.0, 20.0);
+        assert!(!a.toca(&b) && !b.toca(&a));
+    }
+
+    #[test]
+    fn esquina_de_un_girado_dentro_del_otro() {
+        // La esquina izquierda del cuadrado girado queda en x = 24 - 10·√2 ≈ 9.86
+        let a = rectangulo([0.0, 0.0], 0.0, 20.0, 20.0);
+        let b = rectangulo([24.0, 0.0], FRAC_PI_4, 20.0, 20.0);
+        assert!(a.toca(&b) && b.toca(&a));
+        assert!(cerca(a.contacto(&b), [24.0 - 10.0 * 2f32.sqrt(), 0.0]), "{:?}", a.contacto(&b));
+        assert!(!a.toca(&rectangulo([25.0, 0.0], FRAC_PI_4, 20.0, 20.0)));
+    }
+
+    #[test]
+    fn en_cruz_el_contacto_es_el_punto_medio() {
+        let a = rectangulo([0.0, 0.0], 0.0, 40.0, 4.0);
+        let b = rectangulo([0.0, 1.0], FRAC_PI_2, 40.0, 4.0);
+        assert!(a.toca(&b));
+        assert!(cerca(a.contacto(&b), [0.0, 0.5]));
+    }
+
+    #[test]
+    fn detectar_da_cada_par_una_vez_y_en_orden() {
+        // El primer par está sobre el borde de una celda y comparte cuatro de ellas
+        let rectangulos = [
+            rectangulo([45.0, 0.0], 0.0, 20.0, 10.0),
+            rectangulo([500.0, 500.0], 0.0, 20.0, 10.0),
+            rectangulo([55.0, 0.0], 0.0, 20.0, 10.0),
+            rectangulo([300.0, 0.0], 0.0, 20.0, 10.0),
+            rectangulo([310.0, 5.0], FRAC_PI_2, 20.0, 10.0),
+        ];
+        let choques = detectar(&rectangulos);
+        let pares: Vec<(usize, usize)> = choques.iter().map(|&(i, j, _)| (i, j)).collect();
+        assert_eq!(pares, [(0, 2), (3, 4)]);
+        assert!(cerca(choques[0].2, [50.0, 0.0]), "{:?}", choques[0].2);
+    }
+
+    #[test]
+    fn detectar_sin_vehiculos_cerca() {
+        let rectangulos = [
+            rectangulo([0.0, 0.0], 0.0, 20.0, 10.0),
+            rectangulo([100.0, 0.0], 0.0, 20.0, 10.0),
+        ];
+        assert!(detectar(&rectangulos).is_empty());
+        assert!(detectar(&[]).is_empty());
+    }
+}
@@ -1,314 +1,1522 @@
 // controlador.rs
+use crate::colisiones::{self, Rectangulo};
+use crate::coordinacion::PlanCoordinado;
+use crate::demanda;
+use crate::escenario::{Escenario, ProporcionGiros, TipoControl};
+use crate::grabacion::{Cuadro, Grabadora};
+use crate::instantanea::Instantanea;
 use crate::modelo::*;
+use crate::red::{desplazamiento_carril, Red};
+use crate::registro::{Evento, Registro};
 use rand::Rng;
 use std::sync::mpsc;
 use std::thread;
-use std::time::{Duration, Instant};
-use ggez::graphics;
 
-pub fn iniciar_semaforos(compartido: EstadoCompartido) {
+// Controlador de los semáforos de un cruce de la red. Cada cruce tiene su propio
+// hilo y recorre las fases del escenario por su cuenta. Con `plan`, el control
+// fijo sigue el ciclo y el desfase de la coordinación.
+pub fn iniciar_semaforos(compartido: EstadoCompartido, nodo: usize, plan: Option<PlanCoordinado>) {
     thread::spawn(move || {
-        let mut estado_actual = "este".to_string();
-        loop {
-            // Fase verde
-            actualizar_semaforos(&compartido, &estado_actual, EstadoSemaforo::Verde);
-            thread::sleep(Duration::from_secs(DURACION_VERDE));
-
-            // Fase amarillo
-            actualizar_semaforos(&compartido, &estado_actual, EstadoSemaforo::Amarillo);
-            thread::sleep(Duration::from_secs(DURACION_AMARILLO));
-
-            // Cambiar dirección activa
-            estado_actual = if estado_actual == "este" {
-                "norte".to_string()
-            } else {
-                "este".to_string()
+        match compartido.escenario.control {
+            TipoControl::Fijo => ciclo_fijo(&compartido, nodo, plan.as_ref()),
+            TipoControl::Actuado => ciclo_actuado(&compartido, nodo),
+            TipoControl::Adaptativo => ciclo_adaptativo(&compartido, nodo),
+        }
+    });
+}
+
+// Recorre las fases del escenario: verde, amarillo y todo rojo antes de dar
+// verde a la siguiente. Los controladores solo deciden cuándo termina cada
+// verde y qué fase sigue.
+struct Secuencia<'a> {
+    compartido: &'a EstadoCompartido,
+    nodo: usize, // Cruce de la red que controla
+    fase: usize, // Índice en escenario.fases
+    etapa: Etapa,
+    inicio: f32, // Tiempo simulado en que empezó la etapa
+}
+
+impl<'a> Secuencia<'a> {
+    // Sigue desde el estado publicado del cruce: al empezar, la primera fase en
+    // verde (EstadoCompartido::new ya la puso); después de cargar una
+    // instantánea, donde quedó. Devuelve también la memoria del controlador.
+    fn retomar(compartido: &'a EstadoCompartido, nodo: usize) -> (Self, MemoriaControl) {
+        let mut estado = compartido.controladores.lock().unwrap()[nodo].clone();
+        if estado.fase >= compartido.escenario.fases.len() {
+            estado = EstadoControlador::default();
+        }
+        let secuencia = Self { compartido, nodo, fase: estado.fase, etapa: estado.etapa, inicio: estado.inicio };
+        (secuencia, estado.memoria)
+    }
+
+    // Deja el estado del cruce a la vista de las instantáneas
+    fn publicar(&self, memoria: MemoriaControl) {
+        self.compartido.controladores.lock().unwrap()[self.nodo] =
+            EstadoControlador { fase: self.fase, etapa: self.etapa, inicio: self.inicio, memoria };
+    }
+
+    fn en_verde(&self) -> bool {
+        self.etapa == Etapa::Verde
+    }
+
+    fn transcurrido(&self, ahora: f32) -> f32 {
+        ahora - self.inicio
+    }
+
+    // Prioridad para vehículos de emergencia: termina de forma segura la fase en
+    // verde (con su amarillo y todo rojo), da verde a la del acceso por el que
+    // viene y la mantiene hasta que pase la línea de parada. Devuelve true
+    // mientras la secuencia esté tomada por una emergencia.
+    fn atender_emergencia(&mut self, ahora: f32) -> bool {
+        let Some(objetivo) = fase_emergencia(self.compartido, self.nodo) else {
+            return false;
+        };
+
+        if self.en_verde() {
+            if self.fase != objetivo {
+                self.terminar_verde(ahora);
+            }
+        } else {
+            self.avanzar(ahora, |_| objetivo);
+        }
+        true
+    }
+
+    fn terminar_verde(&mut self, ahora: f32) {
+        self.etapa = Etapa::Amarillo;
+        self.inicio = ahora;
+        let direcciones = &self.compartido.escenario.fases[self.fase].direcciones;
+        actualizar_semaforos(self.compartido, self.nodo, direcciones, EstadoSemaforo::Amarillo);
+    }
+
+    // Avanza el entreverde. Al terminar, `siguiente` elige a partir de la fase
+    // actual cuál recibe verde, y se devuelve su índice.
+    fn avanzar(&mut self, ahora: f32, siguiente: impl FnOnce(usize) -> usize) -> Option<usize> {
+        let escenario = &self.compartido.escenario;
+        let transcurrido = self.transcurrido(ahora);
+
+        match self.etapa {
+            Etapa::Amarillo if transcurrido >= escenario.amarillo(self.fase) => {
+                // Todos en rojo mientras se vacía la intersección
+                self.etapa = Etapa::TodoRojo;
+                self.inicio = ahora;
+                actualizar_semaforos(self.compartido, self.nodo, &[], EstadoSemaforo::Rojo);
+                // Sin todo rojo la siguiente fase entra en este mismo tick
+                self.avanzar(ahora, siguiente)
+            }
+            Etapa::TodoRojo if transcurrido >= escenario.todo_rojo(self.fase) => {
+                self.fase = siguiente(self.fase);
+                self.etapa = Etapa::Verde;
+                self.inicio = ahora;
+                cambiar_fase(self.compartido, self.nodo, self.fase);
+                Some(self.fase)
+            }
+            _ => None,
+        }
+    }
+}
+
+// Fase que necesita el vehículo de emergencia más cercano a la línea de parada
+// de uno de los accesos del cruce, si alguno está a menos de DISTANCIA_PREFERENCIA
+fn fase_emergencia(compartido: &EstadoCompartido, nodo: usize) -> Option<usize> {
+    let escenario = &compartido.escenario;
+    let red = &compartido.red;
+    let carros = compartido.carros.lock().unwrap();
+
+    carros.iter()
+        .filter(|carro| carro.tipo.es_emergencia() && carro.recorrido_giro.is_none())
+        .filter(|carro| red.enlaces[carro.enlace].hasta == nodo)
+        .filter_map(|carro| {
+            let frente = carro.direccion.avance(carro.posicion) + carro.tipo.dimensiones()[0] / 2.0;
+            let distancia = red.linea_parada(carro.enlace, escenario) - frente;
+            let fase = escenario.fases.iter().position(|f| f.direcciones.contains(&carro.direccion))?;
+            (0.0..=DISTANCIA_PREFERENCIA).contains(&distancia).then_some((distancia, fase))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, fase)| fase)
+}
+
+// Ciclo fijo: cada fase tiene siempre el mismo verde, haya o no vehículos. Si el
+// cruce está coordinado, los verdes terminan cuando lo indica su plan según el
+// reloj de la simulación, que es el mismo para todos los cruces.
+fn ciclo_fijo(compartido: &EstadoCompartido, nodo: usize, plan: Option<&PlanCoordinado>) {
+    let reloj = compartido.reloj.clone();
+    let escenario = compartido.escenario.clone();
+    // Las fases reciben verde en el orden en que aparecen en el escenario
+    let (mut secuencia, _) = Secuencia::retomar(compartido, nodo);
+
+    while let Some(tick) = reloj.comenzar_tick() {
+        let ahora = reloj.tiempo_de(tick);
+
+        if secuencia.atender_emergencia(ahora) {
+            // El ciclo normal sigue cuando pasa la emergencia
+        } else if secuencia.en_verde() {
+            let termina = match plan {
+                Some(plan) => ahora >= plan.fin_verde(secuencia.fase, secuencia.inicio),
+                None => secuencia.transcurrido(ahora) >= escenario.verde(secuencia.fase),
             };
+            if termina {
+                secuencia.terminar_verde(ahora);
+            }
+        } else {
+            secuencia.avanzar(ahora, |fase| (fase + 1) % escenario.fases.len());
+        }
 
-            // Actualizar estado en compartido - usar scope para minimizar tiempo de lock
-            {
-                let mut direccion = compartido.direccion_activa.lock().unwrap();
-                *direccion = estado_actual.clone();
+        secuencia.publicar(MemoriaControl::Ninguna);
+        reloj.terminar_control();
+    }
+}
+
+// Control actuado: el verde dura al menos `verde_minimo` y se extiende mientras
+// sigan llegando vehículos, hasta `verde_maximo`. Solo se corta si alguien espera
+// en otra fase, y las fases sin vehículos se saltan.
+fn ciclo_actuado(compartido: &EstadoCompartido, nodo: usize) {
+    let reloj = compartido.reloj.clone();
+    let escenario = compartido.escenario.clone();
+    let red = &compartido.red;
+    let parametros = &escenario.actuado;
+    let detectores: Vec<Detector> = red.nodos[nodo].entrantes.iter()
+        .map(|&enlace| Detector::new(red, enlace, &escenario, 0.0, parametros.largo_detector))
+        .collect();
+
+    let (mut secuencia, memoria) = Secuencia::retomar(compartido, nodo);
+    let mut ultima_deteccion = match memoria {
+        MemoriaControl::Actuado { ultima_deteccion } => ultima_deteccion,
+        _ => secuencia.inicio,
+    };
+
+    while let Some(tick) = reloj.comenzar_tick() {
+        let ahora = reloj.tiempo_de(tick);
+
+        // Lectura de los detectores; la física no se mueve hasta terminar_control
+        let mut demanda = [false; Direccion::TODAS.len()];
+        {
+            let carros = compartido.carros.lock().unwrap();
+            for detector in &detectores {
+                demanda[detector.direccion.indice()] = detector.ocupado(&carros);
             }
         }
-    });
+        let fase_con_demanda = |indice: usize| escenario.fases[indice].direcciones.iter().any(|d| demanda[d.indice()]);
+
+        if secuencia.atender_emergencia(ahora) {
+            // Al volver al control normal se cuenta la brecha desde aquí
+            ultima_deteccion = ahora;
+        } else if secuencia.en_verde() {
+            let actual = secuencia.fase;
+            let transcurrido = secuencia.transcurrido(ahora);
+            if fase_con_demanda(actual) {
+                ultima_deteccion = ahora;
+            }
+            let otros_esperan = (0..escenario.fases.len())
+                .any(|i| i != actual && fase_con_demanda(i));
+            let brecha = ahora - ultima_deteccion >= parametros.brecha;
+            let maximo = transcurrido >= parametros.verde_maximo;
+
+            if otros_esperan && transcurrido >= parametros.verde_minimo && (brecha || maximo) {
+                secuencia.terminar_verde(ahora);
+            }
+        } else {
+            // Siguiente fase en orden que tenga vehículos esperando
+            let total = escenario.fases.len();
+            let nueva = secuencia.avanzar(ahora, |actual| {
+                (1..=total)
+                    .map(|salto| (actual + salto) % total)
+                    .find(|&i| fase_con_demanda(i))
+                    .unwrap_or((actual + 1) % total)
+            });
+            if nueva.is_some() {
+                ultima_deteccion = ahora;
+            }
+        }
+
+        secuencia.publicar(MemoriaControl::Actuado { ultima_deteccion });
+        reloj.terminar_control();
+    }
+}
+
+// Control adaptativo: al comenzar cada ciclo se recalculan su duración y el
+// reparto del verde con la fórmula de Webster, usando las llegadas contadas en
+// el ciclo anterior y los vehículos que quedaron en cola
+fn ciclo_adaptativo(compartido: &EstadoCompartido, nodo: usize) {
+    let reloj = compartido.reloj.clone();
+    let escenario = compartido.escenario.clone();
+    let red = &compartido.red;
+    let parametros = &escenario.adaptativo;
+    // Detectores cortos que cuentan los vehículos que pasan sobre ellos
+    let mut contadores: Vec<(Detector, bool)> = red.nodos[nodo].entrantes.iter()
+        .map(|&enlace| (Detector::new(red, enlace, &escenario, parametros.distancia_contador, 1.0), false))
+        .collect();
+
+    let (mut secuencia, memoria) = Secuencia::retomar(compartido, nodo);
+    let mut llegadas = [0usize; Direccion::TODAS.len()];
+    // Vehículos que quedaron sin pasar cuando su fase dejó el verde
+    let mut colas = [0usize; Direccion::TODAS.len()];
+    // Llegadas por segundo, suavizadas. Sin historia se supone tráfico ligero.
+    let mut flujo = [0.0f32; Direccion::TODAS.len()];
+    let mut verdes: Vec<f32> = (0..escenario.fases.len()).map(|i| escenario.verde(i)).collect();
+    let mut inicio_ciclo = secuencia.inicio;
+
+    // Desde una instantánea se sigue con lo medido hasta ese momento
+    if let MemoriaControl::Adaptativo { ocupados, llegadas: l, colas: c, flujo: f, verdes: v, inicio_ciclo: i } = memoria {
+        for ((_, ocupado_antes), ocupado) in contadores.iter_mut().zip(ocupados) {
+            *ocupado_antes = ocupado;
+        }
+        (llegadas, colas, flujo, verdes, inicio_ciclo) = (l, c, f, v, i);
+    }
+
+    while let Some(tick) = reloj.comenzar_tick() {
+        let ahora = reloj.tiempo_de(tick);
+
+        // Cada vez que un contador pasa de libre a ocupado llegó un vehículo
+        {
+            let carros = compartido.carros.lock().unwrap();
+            for (detector, ocupado_antes) in contadores.iter_mut() {
+                let ocupado = detector.ocupado(&carros);
+                if ocupado && !*ocupado_antes {
+                    llegadas[detector.direccion.indice()] += 1;
+                }
+                *ocupado_antes = ocupado;
+            }
+        }
+
+        if secuencia.atender_emergencia(ahora) {
+            // El plan del ciclo sigue cuando pasa la emergencia
+        } else if secuencia.en_verde() {
+            if secuencia.transcurrido(ahora) >= verdes[secuencia.fase] {
+                secuencia.terminar_verde(ahora);
+
+                let direcciones = &escenario.fases[secuencia.fase].direcciones;
+                let carros = compartido.carros.lock().unwrap();
+                for (detector, _) in &contadores {
+                    if direcciones.contains(&detector.direccion) {
+                        colas[detector.direccion.indice()] = detector.cola(&carros);
+                    }
+                }
+            }
+        } else if secuencia.avanzar(ahora, |fase| (fase + 1) % escenario.fases.len()) == Some(0) {
+            // Terminó el ciclo: medir y planear el siguiente
+            let duracion = ahora - inicio_ciclo;
+            // Con pocas llegadas por ciclo la medición es ruidosa: pesa más la historia
+            for (flujo, llegadas) in flujo.iter_mut().zip(llegadas) {
+                *flujo = 0.8 * *flujo + 0.2 * llegadas as f32 / duracion;
+            }
+            llegadas = [0; Direccion::TODAS.len()];
+            inicio_ciclo = ahora;
+
+            // La cola que quedó también tiene que salir durante el próximo ciclo
+            let demanda: [f32; Direccion::TODAS.len()] =
+                std::array::from_fn(|d| flujo[d] + colas[d] as f32 / duracion);
+            verdes = plan_webster(&escenario, &demanda);
+        }
+
+        secuencia.publicar(MemoriaControl::Adaptativo {
+            ocupados: contadores.iter().map(|&(_, ocupado)| ocupado).collect(),
+            llegadas,
+            colas,
+            flujo,
+            verdes: verdes.clone(),
+            inicio_ciclo,
+        });
+        reloj.terminar_control();
+    }
 }
 
-fn actualizar_semaforos(compartido: &EstadoCompartido, direccion: &str, estado: EstadoSemaforo) {
+// Verde de cada fase según Webster. `demanda` son vehículos por segundo en cada dirección.
+fn plan_webster(escenario: &Escenario, demanda: &[f32]) -> Vec<f32> {
+    let parametros = &escenario.adaptativo;
+    let fases = &escenario.fases;
+
+    // Razón de flujo de cada fase: la de su dirección más cargada
+    let razones: Vec<f32> = fases.iter()
+        .map(|fase| fase.direcciones.iter()
+            .map(|d| demanda[d.indice()] / parametros.flujo_saturacion)
+            .fold(0.0, f32::max))
+        .collect();
+    // Si la intersección está saturada el ciclo queda en el máximo
+    let total = razones.iter().sum::<f32>().min(0.95);
+
+    // Tiempo perdido: los entreverdes de todas las fases
+    let perdido: f32 = (0..fases.len()).map(|i| escenario.entreverde(i)).sum();
+    let minimo = (perdido + fases.len() as f32 * parametros.verde_minimo).max(parametros.ciclo_minimo);
+    let ciclo = ((1.5 * perdido + 5.0) / (1.0 - total)).clamp(minimo, parametros.ciclo_maximo.max(minimo));
+
+    let verde_efectivo = ciclo - perdido;
+    let suma: f32 = razones.iter().sum();
+    razones.iter()
+        .map(|&razon| {
+            let proporcion = if suma > 0.0 { razon / suma } else { 1.0 / fases.len() as f32 };
+            (verde_efectivo * proporcion).max(parametros.verde_minimo)
+        })
+        .collect()
+}
+
+// Pone en verde la fase indicada en el cruce y la publica como dirección activa
+fn cambiar_fase(compartido: &EstadoCompartido, nodo: usize, indice: usize) {
+    let direcciones = &compartido.escenario.fases[indice].direcciones;
+
+    // Actualizar estado en compartido - usar scope para minimizar tiempo de lock
+    {
+        let mut direccion = compartido.direccion_activa.lock().unwrap();
+        direccion[nodo] = direcciones.clone();
+    }
+
+    actualizar_semaforos(compartido, nodo, direcciones, EstadoSemaforo::Verde);
+}
+
+// Pone las direcciones indicadas en `estado` y todas las demás en rojo, solo en
+// los semáforos del cruce
+fn actualizar_semaforos(compartido: &EstadoCompartido, nodo: usize, direcciones: &[Direccion], estado: EstadoSemaforo) {
     let mut semaforos = compartido.semaforos.lock().unwrap();
-    for semaforo in semaforos.iter_mut() {
-        if semaforo.direccion == direccion {
+    for semaforo in semaforos.iter_mut().filter(|s| s.nodo == nodo) {
+        if direcciones.contains(&semaforo.direccion) {
             semaforo.estado = estado;
         } else {
             semaforo.estado = EstadoSemaforo::Rojo;
         }
     }
+
+    // Los semáforos peatonales acompañan a la fase en los pasos que ningún
+    // vehículo de la fase cruza de frente; en todo rojo nadie pasa
+    let peatonal = match estado {
+        EstadoSemaforo::Verde => EstadoPeatonal::Pasar,
+        EstadoSemaforo::Amarillo => EstadoPeatonal::Despejar,
+        EstadoSemaforo::Rojo => EstadoPeatonal::NoPasar,
+    };
+    let mut peatonales = compartido.semaforos_peatonales.lock().unwrap();
+    for (paso, senal) in compartido.pasos.iter().zip(peatonales.iter_mut()).filter(|(p, _)| p.nodo == nodo) {
+        *senal = if paso.compatible(direcciones) { peatonal } else { EstadoPeatonal::NoPasar };
+    }
 }
 
-// En controlador.rs, función iniciar_generador_carros
 pub fn iniciar_generador_carros(emisor: mpsc::Sender<Carro>, compartido: EstadoCompartido) {
     thread::spawn(move || {
-        let mut rng = rand::rng();
-        loop {
-            thread::sleep(Duration::from_secs(INTERVALO_APARICION));
-
-            // Añadir aleatoriedad para evitar ráfagas de vehículos
-            if rng.random_bool(0.8) { // 80% de probabilidad de generar
-                let idx = rng.random_range(0..2);
-                let (direccion, pos) = PUNTOS_APARICION[idx];
-                let es_loco = rng.random_bool(0.1);
-                // Verificar si hay espacio suficiente para un nuevo vehículo
-                let espacio_suficiente = {
-                    let carros = compartido.carros.lock().unwrap();
-                    let distancia_minima = 60.0; // Distancia mínima entre vehículos
-
-                    !carros.iter().any(|carro| {
-                        // Solo verificar vehículos en la misma dirección
-                        if carro.direccion != direccion {
-                            return false;
-                        }
+        let reloj = compartido.reloj.clone();
+        let intervalo = compartido.escenario.intervalo_aparicion;
+        // Tiempos de la próxima aparición y ambulancias pendientes: cada accidente
+        // nuevo hace salir una ambulancia; con auxilio, por un camino que pasa por
+        // donde quedó el primero de los vehículos chocados
+        let mut estado = compartido.generador.lock().unwrap().clone();
+        // Con [demanda] los vehículos llegan por cada entrada según su flujo
+        let llegadas = demanda::llegadas(&compartido.escenario, &compartido.red);
 
-                        // Calcular distancia según la dirección
-                        match direccion {
-                            "este" => {
-                                carro.posicion[0] < 100.0 &&
-                                    (carro.posicion[0] - pos[0]).abs() < distancia_minima
-                            },
-                            "norte" => {
-                                carro.posicion[1] > 500.0 &&
-                                    (carro.posicion[1] - pos[1]).abs() < distancia_minima
-                            },
-                            _ => false
-                        }
-                    })
-                };
-
-                if !espacio_suficiente {
-                    continue; // Esperar al siguiente ciclo
+        while let Some(tick) = reloj.comenzar_tick() {
+            {
+                let choques = compartido.choques.lock().unwrap();
+                for choque in &choques[estado.choques_vistos..] {
+                    let ruta = if compartido.escenario.auxilio { compartido.red.ruta_por(choque.enlaces[0]) } else { None };
+                    estado.ambulancias_pendientes.push_back(ruta);
                 }
+                estado.choques_vistos = choques.len();
+            }
 
+            if reloj.tiempo_de(tick) >= estado.proximo_peaton {
+                estado.proximo_peaton += compartido.escenario.intervalo_peatones;
+                generar_peaton(&compartido);
+            }
 
+            // El canal solo se cierra cuando la física ya terminó
+            if let Some(llegadas) = &llegadas {
+                let ahora = reloj.tiempo_de(tick);
+                // Las ambulancias no esperan a la demanda: salen apenas hay espacio
+                if let Some(&ruta) = estado.ambulancias_pendientes.front()
+                    && intentar_generar(&emisor, &compartido, Some(TipoVehiculo::Ambulancia), ruta).unwrap_or(false) {
+                    estado.ambulancias_pendientes.pop_front();
+                }
 
-                let tipo_vehiculo = match rng.random_range(0..3) {
-                    0 => TipoVehiculo::Automovil,
-                    1 => TipoVehiculo::Camioneta,
-                    _ => TipoVehiculo::Camion,
-                };
-
-                let color = match tipo_vehiculo {
-                    TipoVehiculo::Automovil => graphics::Color::from_rgb(
-                        rng.random_range(100..255),
-                        rng.random_range(100..255),
-                        rng.random_range(100..255)
-                    ),
-                    TipoVehiculo::Camioneta => graphics::Color::from_rgb(
-                        rng.random_range(50..150),
-                        rng.random_range(50..150),
-                        rng.random_range(50..150)
-                    ),
-                    TipoVehiculo::Camion => graphics::Color::from_rgb(
-                        rng.random_range(0..100),
-                        rng.random_range(0..100),
-                        rng.random_range(0..100)
-                    ),
-                };
-
-                // Variar ligeramente la velocidad para evitar agrupaciones
-
-                let velocidad_ajustada = VELOCIDAD_VEHICULO;
-
-                if let Err(_) = emisor.send(Carro {
-                    posicion: pos,
-                    direccion,
-                    color,
-                    velocidad: velocidad_ajustada as f32,
-                    tipo: tipo_vehiculo,
-                    loco: es_loco,
-                }){
-                    // Canal cerrado, terminar hilo
-                    break;
+                {
+                    let mut rng = compartido.rng.lock().unwrap();
+                    for (i, acceso) in llegadas.iter().enumerate() {
+                        while estado.proximas_llegadas[i] <= ahora {
+                            estado.en_espera[i] += 1;
+                            estado.proximas_llegadas[i] = acceso.siguiente(&mut rng, estado.proximas_llegadas[i]);
+                        }
+                    }
+                }
+                // Los que no caben quedan esperando fuera de la red, en orden de llegada
+                for (i, acceso) in llegadas.iter().enumerate() {
+                    if estado.en_espera[i] > 0 && generar_en(&emisor, &compartido, acceso.enlace).unwrap_or(false) {
+                        estado.en_espera[i] -= 1;
+                    }
+                }
+            } else if reloj.tiempo_de(tick) >= estado.proxima_aparicion {
+                estado.proxima_aparicion += intervalo;
+                let ambulancia = estado.ambulancias_pendientes.front().copied();
+                let tipo = ambulancia.map(|_| TipoVehiculo::Ambulancia);
+                let generado = intentar_generar(&emisor, &compartido, tipo, ambulancia.flatten()).unwrap_or(false);
+                if generado && tipo.is_some() {
+                    estado.ambulancias_pendientes.pop_front();
                 }
             }
+
+            // Para las instantáneas
+            *compartido.generador.lock().unwrap() = estado.clone();
+            reloj.terminar_control();
         }
     });
 }
-pub fn iniciar_motor_fisica(compartido: EstadoCompartido) {
+
+// Pone un peatón en la acera, en un extremo al azar de uno de los pasos. Los
+// peatones no pasan por el canal: los mueve la física desde el tick siguiente.
+fn generar_peaton(compartido: &EstadoCompartido) {
+    if compartido.pasos.is_empty() {
+        return;
+    }
+
+    let mut rng = compartido.rng.lock().unwrap();
+    let indice = rng.random_range(0..compartido.pasos.len());
+    let paso = &compartido.pasos[indice];
+    let [mut desde, mut hasta] = paso.extremos();
+    if rng.random_bool(0.5) {
+        std::mem::swap(&mut desde, &mut hasta);
+    }
+
+    // Cada uno cruza por un punto distinto de la franja
+    let corrimiento = rng.random_range(-1.0..1.0) * (ANCHO_PASO / 2.0 - RADIO_PEATON);
+    let [x, y] = paso.acceso.vector();
+    let correr = |p: [f32; 2]| [p[0] + x * corrimiento, p[1] + y * corrimiento];
+
+    let color = Color::from_rgb(rng.random_range(50..255), rng.random_range(50..255), rng.random_range(50..255));
+    compartido.peatones.lock().unwrap().push(Peaton {
+        posicion: correr(desde),
+        paso: indice,
+        destino: correr(hasta),
+        cruzando: false,
+        color,
+    });
+}
+
+// Genera un vehículo en un extremo de la red al azar. Con `tipo` se fuerza el tipo
+// de vehículo y se genera siempre que haya espacio, y con `ruta` se fuerzan el
+// enlace de entrada y el extremo de salida. Devuelve true si se generó.
+fn intentar_generar(emisor: &mpsc::Sender<Carro>, compartido: &EstadoCompartido, tipo: Option<TipoVehiculo>, ruta: Option<(usize, usize)>) -> Result<bool, mpsc::SendError<Carro>> {
+    let mut rng = compartido.rng.lock().unwrap();
+
+    // Añadir aleatoriedad para evitar ráfagas de vehículos
+    if tipo.is_some() || rng.random_bool(0.8) { // 80% de probabilidad de generar
+        let red = &compartido.red;
+        let enlace = match ruta {
+            Some((entrada, _)) => entrada,
+            None => red.entradas[rng.random_range(0..red.entradas.len())],
+        };
+        return crear_vehiculo(emisor, compartido, &mut rng, enlace, tipo, ruta.map(|(_, destino)| destino));
+    }
+
+    Ok(false)
+}
+
+// Genera un vehículo cualquiera en la entrada dada, si hay espacio
+fn generar_en(emisor: &mpsc::Sender<Carro>, compartido: &EstadoCompartido, enlace: usize) -> Result<bool, mpsc::SendError<Carro>> {
+    let mut rng = compartido.rng.lock().unwrap();
+    crear_vehiculo(emisor, compartido, &mut rng, enlace, None, None)
+}
+
+// Pone un vehículo al comienzo del enlace de entrada, en un carril con espacio.
+// Sin `tipo` ni `destino` se sortean.
+fn crear_vehiculo(emisor: &mpsc::Sender<Carro>, compartido: &EstadoCompartido, rng: &mut Aleatorio, enlace: usize, tipo: Option<TipoVehiculo>, destino: Option<usize>) -> Result<bool, mpsc::SendError<Carro>> {
+    let red = &compartido.red;
+    let direccion = red.enlaces[enlace].direccion;
+    let inicio = direccion.avance(red.inicio_carril(enlace, 0));
+    let es_loco = rng.random_bool(0.1);
+    // Verificar en qué carriles hay espacio suficiente para un nuevo vehículo:
+    // carril y distancia al vehículo más cercano en él
+    let libres: Vec<(usize, f32)> = {
+        let carros = compartido.carros.lock().unwrap();
+        let distancia_minima = 60.0; // Distancia mínima entre vehículos
+
+        // Solo verificar vehículos en el mismo carril
+        (0..red.enlaces[enlace].carriles)
+            .map(|carril| {
+                let linea = red.enlaces[enlace].lineas[carril];
+                let cercano = carros.iter()
+                    .filter(|carro| red.linea_de(carro) == linea)
+                    .map(|carro| (direccion.avance(carro.posicion) - inicio).abs())
+                    .fold(f32::INFINITY, f32::min);
+                (carril, cercano)
+            })
+            .filter(|&(_, cercano)| cercano >= distancia_minima)
+            .collect()
+    };
+
+    if libres.is_empty() {
+        return Ok(false); // Esperar al siguiente ciclo
+    }
+
+    let tipo_vehiculo = match tipo {
+        Some(tipo) => tipo,
+        None if rng.random_bool(compartido.escenario.probabilidad_emergencia as f64) => {
+            if rng.random_bool(0.5) { TipoVehiculo::Ambulancia } else { TipoVehiculo::Policia }
+        }
+        None => match rng.random_range(0..3) {
+            0 => TipoVehiculo::Automovil,
+            1 => TipoVehiculo::Camioneta,
+            _ => TipoVehiculo::Camion,
+        },
+    };
+
+    let color = match tipo_vehiculo {
+        TipoVehiculo::Automovil => Color::from_rgb(
+            rng.random_range(100..255),
+            rng.random_range(100..255),
+            rng.random_range(100..255)
+        ),
+        TipoVehiculo::Camioneta => Color::from_rgb(
+            rng.random_range(50..150),
+            rng.random_range(50..150),
+            rng.random_range(50..150)
+        ),
+        TipoVehiculo::Camion => Color::from_rgb(
+            rng.random_range(0..100),
+            rng.random_range(0..100),
+            rng.random_range(0..100)
+        ),
+        TipoVehiculo::Ambulancia => Color::WHITE,
+        TipoVehiculo::Policia => Color::from_rgb(20, 40, 160),
+    };
+
+    // Los vehículos más pesados quieren ir más despacio y los de emergencia más rápido
+    let velocidad_deseada = compartido.escenario.velocidad_vehiculo * tipo_vehiculo.factor_velocidad();
+
+    // Extremo por el que sale y movimiento que hará en el primer cruce
+    let destino = match destino {
+        Some(destino) => destino,
+        None => elegir_destino(red, enlace, &compartido.escenario.proporcion_giros, rng),
+    };
+    let movimiento = red.maniobra(enlace, destino);
+
+    // Entra por el carril de su giro si está libre; si no, por el más despejado
+    let carril = red.carril_para(enlace, movimiento)
+        .filter(|preferido| libres.iter().any(|&(c, _)| c == *preferido))
+        .unwrap_or_else(|| libres.iter().max_by(|a, b| a.1.total_cmp(&b.1)).unwrap().0);
+
+    emisor.send(Carro {
+        id: *compartido.contador_generados.lock().unwrap(),
+        aparicion: compartido.reloj.tiempo(),
+        salida: None,
+        origen: enlace,
+        posicion: red.inicio_carril(enlace, carril),
+        angulo: direccion.angulo(),
+        direccion,
+        enlace,
+        carril,
+        destino,
+        movimiento,
+        recorrido_giro: None,
+        color,
+        velocidad: velocidad_deseada,
+        velocidad_deseada,
+        tipo: tipo_vehiculo,
+        loco: es_loco && !tipo_vehiculo.es_emergencia(),
+        orillado: false,
+        siniestro: None,
+        demora: 0.0,
+        paradas: 0,
+        detenido: false,
+    })?;
+
+    *compartido.contador_generados.lock().unwrap() += 1;
+    Ok(true)
+}
+
+// Sortea el recorrido desde el enlace de entrada: en cada cruce se gira según
+// las proporciones del escenario, hasta llegar a un extremo. Si el giro sorteado
+// no existe en ese cruce se sigue recto o por donde se pueda.
+fn elegir_destino(red: &Red, entrada: usize, giros: &ProporcionGiros, rng: &mut Aleatorio) -> usize {
+    let mut enlace = entrada;
+    // Con vueltas en círculo se corta y se sale por el extremo más cercano
+    for _ in 0..red.enlaces.len() {
+        let nodo = red.enlaces[enlace].hasta;
+        if red.es_extremo(nodo) {
+            return nodo;
+        }
+
+        let sorteo: f32 = rng.random();
+        let movimiento = if sorteo < giros.izquierda {
+            Movimiento::Izquierda
+        } else if sorteo < giros.izquierda + giros.derecha {
+            Movimiento::Derecha
+        } else {
+            Movimiento::Recto
+        };
+
+        // Un nodo que no es extremo siempre tiene alguna salida sin volver atrás
+        enlace = [movimiento, Movimiento::Recto, Movimiento::Izquierda, Movimiento::Derecha]
+            .into_iter()
+            .find_map(|m| red.enlace_tras(enlace, m))
+            .unwrap();
+    }
+    // validar() garantiza que desde cada entrada se llega a algún extremo
+    red.extremo_cercano(entrada).unwrap()
+}
+
+// Devuelve el hilo para esperar a que termine de escribir el registro
+pub fn iniciar_motor_fisica(compartido: EstadoCompartido, receptor: mpsc::Receiver<Carro>, mut registro: Option<Registro>, mut grabadora: Option<Grabadora>) -> thread::JoinHandle<()> {
     thread::spawn(move || {
-        let mut ultimo_update = Instant::now();
+        let reloj = compartido.reloj.clone();
+        let red = compartido.red.clone();
+        // Estados de los semáforos en el tick anterior, para registrar los cambios
+        let mut estados_anteriores: Vec<EstadoSemaforo> = Vec::new();
 
-        loop {
-            // Control de velocidad de actualización para reducir uso de CPU
-            let ahora = Instant::now();
-            let delta_ms = ahora.duration_since(ultimo_update).as_millis() as f32;
+        while let Some(tick) = reloj.comenzar_tick() {
+            // La física trabaja cuando semáforos y generador terminaron este tick
+            reloj.terminar_control();
+            let ahora_simulado = reloj.tiempo_de(tick);
 
-            if delta_ms < (1000.0 / FPS_SIMULACION as f32) {
-                let espera = (1000.0 / FPS_SIMULACION as f32 - delta_ms) as u64;
-                thread::sleep(Duration::from_millis(espera));
-                continue;
+            // Estado del semáforo al final de cada enlace; los que llegan a un
+            // extremo o a un cruce sin semáforos no tienen
+            let mut senal_por_enlace = vec![None; red.enlaces.len()];
+            {
+                let semaforos = compartido.semaforos.lock().unwrap();
+                for (i, semaforo) in semaforos.iter().enumerate() {
+                    senal_por_enlace[semaforo.enlace] = Some(semaforo.estado);
+                    if let Some(registro) = registro.as_mut()
+                        && estados_anteriores.get(i) != Some(&semaforo.estado) {
+                        registro.semaforo(ahora_simulado, semaforo);
+                    }
+                }
+                estados_anteriores = semaforos.iter().map(|s| s.estado).collect();
             }
 
-            ultimo_update = ahora;
-
-            // Factor de movimiento basado en tiempo para movimiento uniforme
-            let factor_movimiento = delta_ms / 1000.0;
+            // Incorporar los vehículos generados en este tick
+            let nuevos_carros: Vec<_> = receptor.try_iter().collect();
+            if let Some(registro) = registro.as_mut() {
+                for carro in &nuevos_carros {
+                    registro.vehiculo(ahora_simulado, Evento::Aparicion, carro, senal_por_enlace[carro.enlace]);
+                }
+            }
+            if !nuevos_carros.is_empty() {
+                compartido.carros.lock().unwrap().extend(nuevos_carros);
+            }
 
-            // Actualizar estado de vehículos
-            let mut removidos = Vec::new();
-            let mut accidentes = Vec::new();
+            // Paso fijo del reloj simulado
+            let factor_movimiento = reloj.paso();
 
-            // Recolectar información de intersección antes de mover los vehículos
-            let carros_en_interseccion = {
+            // Peatones: esperan en la acera hasta que su semáforo da paso y cruzan en línea recta
+            let pasos_ocupados: Vec<&PasoPeatonal> = {
                 let carros = compartido.carros.lock().unwrap();
-                // Recopilar los índices y direcciones de los vehículos en la intersección
-                carros.iter().enumerate()
-                    .filter_map(|(idx, carro)| {
-                        if carro.posicion[0] >= 300.0 && carro.posicion[0] <= 350.0 &&
-                            carro.posicion[1] >= 300.0 && carro.posicion[1] <= 350.0 {
-                            Some((idx, carro.direccion.clone(), carro.loco))
+                let senales = compartido.semaforos_peatonales.lock().unwrap();
+                let mut peatones = compartido.peatones.lock().unwrap();
+                // La señal da el paso, pero antes se espera a que pasen los vehículos que ya vienen
+                let despejados: Vec<bool> = compartido.pasos.iter()
+                    .map(|paso| paso.despejado(carros.iter().map(|c| &c.posicion)))
+                    .collect();
+                for peaton in peatones.iter_mut() {
+                    if !peaton.cruzando && senales[peaton.paso] == EstadoPeatonal::Pasar && despejados[peaton.paso] {
+                        peaton.cruzando = true;
+                    }
+                    if peaton.cruzando {
+                        let dx = peaton.destino[0] - peaton.posicion[0];
+                        let dy = peaton.destino[1] - peaton.posicion[1];
+                        let falta = (dx * dx + dy * dy).sqrt();
+                        let paso = VELOCIDAD_PEATON * factor_movimiento;
+                        if falta <= paso {
+                            peaton.posicion = peaton.destino;
                         } else {
-                            None
-                        }
-                    })
-                    .collect::<Vec<_>>()
-            };
-
-            // Detectar colisiones basadas en la información recopilada
-            for (i, dir_i, loco_i) in &carros_en_interseccion {
-                if *loco_i { // Solo los vehículos locos pueden causar accidentes
-                    for (j, dir_j, _) in &carros_en_interseccion {
-                        if i != j && dir_i != dir_j {
-                            // Colisión detectada
-                            accidentes.push(*i);
-                            accidentes.push(*j);
+                            peaton.posicion[0] += dx / falta * paso;
+                            peaton.posicion[1] += dy / falta * paso;
                         }
                     }
                 }
-            }
+                // Los que llegan a la acera de enfrente terminan su recorrido
+                peatones.retain(|p| p.posicion != p.destino);
+
+                compartido.pasos.iter().enumerate()
+                    .filter(|&(i, paso)| peatones.iter().any(|p| p.paso == i && paso.sobre_calzada(p.posicion)))
+                    .map(|(_, paso)| paso)
+                    .collect()
+            };
+
+            // Actualizar estado de vehículos
+            let mut removidos = Vec::new();
 
             // Scope para minimizar tiempo de lock - procesamiento principal
             {
                 let mut carros = compartido.carros.lock().unwrap();
-                let semaforos = compartido.semaforos.lock().unwrap();
 
-                // Ordenar los carros por posición según dirección
+                // Cambios de carril antes de ordenar, para que cada uno quede en la línea nueva
+                cambiar_carriles(&mut carros, &red);
+
+                // Ordenar los carros por carril y, en cada uno, del más adelantado al último
                 carros.sort_by(|a, b| {
-                    match a.direccion.cmp(&b.direccion) {
-                        std::cmp::Ordering::Equal => {
-                            // Si van en la misma dirección, ordenar por posición
-                            match a.direccion {
-                                "este" => b.posicion[0].partial_cmp(&a.posicion[0]).unwrap_or(std::cmp::Ordering::Equal),
-                                "norte" => a.posicion[1].partial_cmp(&b.posicion[1]).unwrap_or(std::cmp::Ordering::Equal),
-                                _ => std::cmp::Ordering::Equal
-                            }
-                        },
+                    match red.linea_de(a).cmp(&red.linea_de(b)) {
+                        std::cmp::Ordering::Equal => b.direccion.avance(b.posicion)
+                            .partial_cmp(&a.direccion.avance(a.posicion))
+                            .unwrap_or(std::cmp::Ordering::Equal),
                         other => other
                     }
                 });
 
-                // Mantener un registro de los vehículos y sus posiciones para detectar colisiones
-                let mut posiciones_este = Vec::new();
-                let mut posiciones_norte = Vec::new();
+                // Avance de los vehículos de emergencia que van por cada carril
+                let mut emergencias = vec![Vec::new(); red.num_lineas];
+                for carro in carros.iter().filter(|c| c.tipo.es_emergencia() && c.recorrido_giro.is_none()) {
+                    emergencias[red.linea_de(carro)].push(carro.direccion.avance(carro.posicion));
+                }
+
+                // Vehículos chocados que siguen en la vía. Los de emergencia que llegan
+                // hasta uno lo despejan en poco tiempo.
+                let unidades: Vec<[f32; 2]> = carros.iter()
+                    .filter(|c| c.tipo.es_emergencia() && c.siniestro.is_none())
+                    .map(|c| c.posicion)
+                    .collect();
+                for carro in carros.iter_mut() {
+                    let posicion = carro.posicion;
+                    let Some(siniestro) = carro.siniestro.as_mut() else { continue };
+                    let atendido = unidades.iter()
+                        .any(|u| (u[0] - posicion[0]).hypot(u[1] - posicion[1]) <= DISTANCIA_AUXILIO);
+                    if compartido.escenario.auxilio && atendido {
+                        siniestro.retiro = siniestro.retiro.min(ahora_simulado + TIEMPO_AUXILIO);
+                    }
+                }
+                let siniestros: Vec<Rectangulo> = carros.iter()
+                    .filter(|c| c.siniestro.is_some())
+                    .map(Rectangulo::de)
+                    .collect();
+                let mut retirados = Vec::new();
+
+                // Vehículos ya procesados en cada carril: avance de su parte trasera,
+                // velocidad y si está orillado
+                let mut ocupados: Vec<Vec<(f32, f32, bool)>> = vec![Vec::new(); red.num_lineas];
+
+                // Demora de este tick sumando todos los vehículos
+                let mut demora = 0.0;
+
+                // Vehículos detenidos antes de la línea en cada acceso, para la cola máxima
+                let mut estadisticas = compartido.estadisticas.lock().unwrap();
+                let mut colas = vec![0; estadisticas.accesos.len()];
 
                 // Procesar cada vehículo
                 for (i, carro) in carros.iter_mut().enumerate() {
-                    let semaforo = semaforos.iter()
-                        .find(|s| s.direccion == carro.direccion)
-                        .unwrap();
-
-                    // Determinar el espacio necesario según el tipo de vehículo
-                    let espacio_necesario = match carro.tipo {
-                        TipoVehiculo::Automovil => 50.0,
-                        TipoVehiculo::Camioneta => 50.0,
-                        TipoVehiculo::Camion => 60.0,
-                    };
-
-                    // Verificar si hay vehículos adelante que bloqueen el paso
-                    let hay_obstaculo = match carro.direccion {
-                        "este" => posiciones_este.iter().any(|&pos|
-                            pos > carro.posicion[0] && pos - carro.posicion[0] < espacio_necesario),
-                        "norte" => posiciones_norte.iter().any(|&pos|
-                            pos < carro.posicion[1] && carro.posicion[1] - pos < espacio_necesario),
-                        _ => false
-                    };
-
-                    let puede_avanzar = if hay_obstaculo {
-                        false
-                    } else {
-                        match semaforo.estado {
-                            EstadoSemaforo::Verde => true,
-                            EstadoSemaforo::Amarillo | EstadoSemaforo::Rojo => {
-                                if carro.loco {
-                                    true
-                                } else {
-                                    match carro.direccion {
-                                        "este" => carro.posicion[0] > POSICION_SEMAFORO_VERTICAL ||
-                                            carro.posicion[0] < POSICION_SEMAFORO_VERTICAL - 20.0,
-                                        "norte" => carro.posicion[1] < POSICION_SEMAFORO_HORIZONTAL ||
-                                            carro.posicion[1] > POSICION_SEMAFORO_HORIZONTAL + 20.0,
-                                        "oeste" => carro.posicion[0] < POSICION_SEMAFORO_VERTICAL,
-                                        "sur" => carro.posicion[1] > POSICION_SEMAFORO_HORIZONTAL,
-                                        _ => false
-                                    }
-                                }
-                            }
+                    let mi_avance = carro.direccion.avance(carro.posicion);
+                    let frente = mi_avance + carro.tipo.dimensiones()[0] / 2.0;
+                    let carril = red.linea_de(carro);
+
+                    // Los chocados no se mueven y bloquean su carril hasta que los retiran
+                    if let Some(siniestro) = carro.siniestro {
+                        ocupados[carril].push((mi_avance - carro.tipo.dimensiones()[0] / 2.0, 0.0, false));
+                        if ahora_simulado >= siniestro.retiro {
+                            retirados.push(i);
                         }
-                    };
-
-                    if puede_avanzar {
-                        // Movimiento ajustado por tiempo para mantener velocidad constante
-                        match carro.direccion {
-                            "este" => carro.posicion[0] += carro.velocidad * factor_movimiento,
-                            "norte" => carro.posicion[1] -= carro.velocidad * factor_movimiento,
-                            _ => {}
+                        continue;
+                    }
+
+                    // Semáforo y línea de parada al final del enlace, si los hay
+                    let senal = senal_por_enlace[carro.enlace];
+                    let linea = red.linea_parada(carro.enlace, &compartido.escenario);
+
+                    // Se orilla y espera si se acerca uno de emergencia por detrás,
+                    // salvo que ya esté pasando la línea de parada
+                    carro.orillado = !carro.tipo.es_emergencia() && carro.recorrido_giro.is_none()
+                        && senal.is_some() && frente < linea
+                        && emergencias[carril].iter()
+                            .any(|&otro| otro < mi_avance && mi_avance - otro < DISTANCIA_CEDER);
+
+                    // El vehículo más cercano que va adelante en el mismo carril; los de
+                    // emergencia pasan de largo a los orillados
+                    let es_emergencia = carro.tipo.es_emergencia();
+                    let mut obstaculo = ocupados[carril].iter()
+                        .filter(|(trasera, _, orillado)| *trasera > mi_avance && !(es_emergencia && *orillado))
+                        .min_by(|a, b| a.0.total_cmp(&b.0))
+                        .map(|&(trasera, velocidad, _)| (trasera - frente, velocidad));
+
+                    // En amarillo o rojo la línea de parada es un obstáculo detenido, salvo
+                    // para los locos y los que ya van girando dentro de la intersección
+                    let estado = senal.unwrap_or(EstadoSemaforo::Verde);
+                    if estado != EstadoSemaforo::Verde && !carro.loco && carro.recorrido_giro.is_none() {
+                        let distancia = linea - frente;
+                        if distancia >= 0.0 && puede_detenerse(carro, distancia, estado)
+                            && obstaculo.is_none_or(|(hueco, _)| distancia < hueco) {
+                            obstaculo = Some((distancia, 0.0));
                         }
                     }
 
-                    // Registrar la posición para el siguiente vehículo
-                    match carro.direccion {
-                        "este" => posiciones_este.push(carro.posicion[0]),
-                        "norte" => posiciones_norte.push(carro.posicion[1]),
-                        _ => {}
+                    // Ceder el paso a los peatones que están cruzando
+                    let cediendo = if carro.loco { None } else { distancia_a_peatones(carro, frente, &pasos_ocupados, &red) };
+                    if let Some(distancia) = cediendo.filter(|&d| obstaculo.is_none_or(|(hueco, _)| d < hueco)) {
+                        obstaculo = Some((distancia, 0.0));
+                    }
+
+                    // Detenerse detrás de los chocados que ocupan su camino, en cualquier carril
+                    let bloqueo = distancia_a_siniestros(carro, frente, &siniestros, &red);
+                    if let Some(distancia) = bloqueo.filter(|&d| obstaculo.is_none_or(|(hueco, _)| d < hueco)) {
+                        obstaculo = Some((distancia, 0.0));
+                    }
+
+                    let mut aceleracion = aceleracion_idm(carro, obstaculo);
+                    if carro.orillado {
+                        aceleracion = aceleracion.min(-carro.tipo.frenado_comodo());
+                    }
+                    let perdido = factor_movimiento * (1.0 - carro.velocidad / carro.velocidad_deseada).max(0.0);
+                    demora += perdido;
+                    carro.demora += perdido;
+                    // La demora en un acceso semaforizado cuenta hasta pasar la línea de parada
+                    let acceso = estadisticas.acceso_de(carro.enlace)
+                        .filter(|_| carro.recorrido_giro.is_none() && frente < linea);
+                    if let Some(a) = acceso {
+                        estadisticas.accesos[a].demora += perdido as f64;
+                    }
+                    let enlace_antes = carro.enlace;
+                    let velocidad_nueva = (carro.velocidad + aceleracion * factor_movimiento).max(0.0);
+                    let mut distancia = (carro.velocidad + velocidad_nueva) / 2.0 * factor_movimiento;
+                    // Nunca avanzar más allá de lo que tiene adelante
+                    if let Some((hueco, _)) = obstaculo {
+                        distancia = distancia.min(hueco.max(0.0));
                     }
+                    carro.velocidad = velocidad_nueva;
+                    mover_carro(carro, distancia, &red);
 
-                    // Marcar para eliminación si está fuera de pantalla
-                    if carro.posicion[0] > 650.0 || carro.posicion[1] < -50.0 {
+                    if carro.velocidad < VELOCIDAD_DETENIDO && !carro.detenido {
+                        carro.paradas += 1;
+                        carro.detenido = true;
+                        if let Some(registro) = registro.as_mut() {
+                            registro.vehiculo(ahora_simulado, Evento::Parada, carro, senal);
+                        }
+                    } else if carro.velocidad >= VELOCIDAD_REANUDA {
+                        carro.detenido = false;
+                    }
+                    // Pasó la línea de parada del acceso, o sigue esperando en la cola
+                    if let Some(a) = acceso {
+                        let nuevo_frente = carro.direccion.avance(carro.posicion) + carro.tipo.dimensiones()[0] / 2.0;
+                        if carro.enlace != enlace_antes || carro.recorrido_giro.is_some() || nuevo_frente >= linea {
+                            estadisticas.accesos[a].vehiculos += 1;
+                        } else if carro.velocidad < VELOCIDAD_DETENIDO {
+                            colas[a] += 1;
+                        }
+                    }
+
+                    // Registrar la posición para el siguiente vehículo, en el carril en que quedó
+                    let trasera = carro.direccion.avance(carro.posicion) - carro.tipo.dimensiones()[0] / 2.0;
+                    ocupados[red.linea_de(carro)].push((trasera, carro.velocidad, carro.orillado));
+
+                    // Marcar para eliminación si ya salió del mapa por un extremo
+                    let final_enlace = red.enlaces[carro.enlace].hasta;
+                    if red.es_extremo(final_enlace)
+                        && carro.direccion.avance(carro.posicion) > carro.direccion.avance(red.nodos[final_enlace].posicion) + 50.0 {
+                        carro.salida = Some(ahora_simulado);
+                        estadisticas.registrar_salida(carro);
+                        if let Some(registro) = registro.as_mut() {
+                            registro.vehiculo(ahora_simulado, Evento::Salida, carro, senal_por_enlace[carro.enlace]);
+                        }
                         removidos.push(i);
                     }
                 }
+                estadisticas.registrar_colas(&colas);
+                drop(estadisticas);
+
+                *compartido.demora_total.lock().unwrap() += demora as f64;
+
+                // Peatones alcanzados en la calzada por un vehículo (los que no ceden el paso)
+                let mut peatones = compartido.peatones.lock().unwrap();
+                let antes = peatones.len();
+                let pasos = &compartido.pasos;
+                peatones.retain(|peaton| {
+                    !pasos[peaton.paso].sobre_calzada(peaton.posicion)
+                        || !carros.iter().any(|carro| carro.siniestro.is_none() && toca_peaton(carro, peaton.posicion))
+                });
+                *compartido.contador_atropellos.lock().unwrap() += antes - peatones.len();
+                drop(peatones);
+
+                // Choques con las posiciones nuevas. Solo los vehículos locos pueden
+                // causarlos; los de emergencia cruzan con cuidado y no chocan. Los
+                // chocados quedan en la vía un tiempo según la gravedad del choque.
+                let rectangulos: Vec<_> = carros.iter().map(Rectangulo::de).collect();
+                let mut accidentados = Vec::new();
+                let mut choques = Vec::new();
+                for (i, j, contacto) in colisiones::detectar(&rectangulos) {
+                    let (a, b) = (&carros[i], &carros[j]);
+                    let alguno_loco = a.loco || b.loco;
+                    let hay_emergencia = a.tipo.es_emergencia() || b.tipo.es_emergencia();
+                    let ya_chocado = a.siniestro.is_some() || b.siniestro.is_some();
+                    // Cada vehículo choca una sola vez
+                    if !alguno_loco || hay_emergencia || ya_chocado || accidentados.contains(&i) || accidentados.contains(&j) {
+                        continue;
+                    }
+                    let [va, vb] = [a, b].map(|c| {
+                        let (sin, cos) = c.angulo.sin_cos();
+                        [c.velocidad * cos, c.velocidad * sin]
+                    });
+                    let gravedad = Gravedad::por_velocidad((va[0] - vb[0]).hypot(va[1] - vb[1]));
+                    accidentados.extend([i, j]);
+                    choques.push(Choque {
+                        posicion: contacto,
+                        tiempo: ahora_simulado,
+                        tipos: [a.tipo, b.tipo],
+                        enlaces: [a.enlace, b.enlace],
+                        gravedad,
+                    });
+                    for k in [i, j] {
+                        let carro = &mut carros[k];
+                        // Con la velocidad que traía al chocar
+                        if let Some(registro) = registro.as_mut() {
+                            registro.vehiculo(ahora_simulado, Evento::Choque, carro, senal_por_enlace[carro.enlace]);
+                        }
+                        carro.velocidad = 0.0;
+                        carro.orillado = false;
+                        carro.siniestro = Some(Siniestro { gravedad, retiro: ahora_simulado + gravedad.tiempo_despeje() });
+                    }
+                }
+                if !choques.is_empty() {
+                    *compartido.contador_accidentes.lock().unwrap() += choques.len();
+                    compartido.choques.lock().unwrap().extend(choques);
+                }
 
-                // Eliminar vehículos fuera de pantalla (más eficiente eliminar de atrás hacia adelante)
+                // Eliminar vehículos fuera de pantalla y chocados ya retirados (más
+                // eficiente eliminar de atrás hacia adelante)
+                *compartido.contador_salidas.lock().unwrap() += removidos.len();
+                removidos.extend(retirados);
                 removidos.sort_by(|a, b| b.cmp(a));
                 for idx in removidos {
-                    if idx < carros.len() {
-                        carros.swap_remove(idx); // swap_remove es más eficiente que remove
+                    carros.swap_remove(idx); // swap_remove es más eficiente que remove
+                }
+
+                // Posición de los que siguen en la vía al terminar el tick
+                if let Some(registro) = registro.as_mut() {
+                    for carro in carros.iter() {
+                        registro.vehiculo(ahora_simulado, Evento::Posicion, carro, senal_por_enlace[carro.enlace]);
                     }
                 }
             }
 
-            // Actualizar timestamp de última actualización
-            {
-                let mut ultima = compartido.ultima_actualizacion.lock().unwrap();
-                *ultima = ahora;
+            // Si el archivo falla se avisa una vez y se deja de registrar
+            if let Some(Err(e)) = registro.as_mut().map(Registro::terminar_tick) {
+                eprintln!("No se pudo escribir el registro: {}", e);
+                registro = None;
+            }
+            if let Some(actual) = grabadora.as_mut() && actual.toca(tick)
+                && let Err(e) = actual.grabar(&Cuadro::de(&compartido, ahora_simulado)) {
+                eprintln!("No se pudo escribir la grabación: {}", e);
+                grabadora = None;
             }
 
-            // Gestionar accidentes - ahora con su propio scope y lock de carros
-            if !accidentes.is_empty() {
-                let mut contador = compartido.contador_accidentes.lock().unwrap();
-                *contador += 1;
-
-                // Lock carros de nuevo para eliminar los vehículos accidentados
-                {
-                    let mut carros = compartido.carros.lock().unwrap();
-                    // Eliminar vehículos accidentados
-                    accidentes.sort_by(|a, b| b.cmp(a));
-                    accidentes.dedup(); // Eliminar duplicados
-                    for idx in accidentes {
-                        if idx < carros.len() {
-                            carros.swap_remove(idx);
-                        }
-                    }
+            // Instantánea pedida: los demás hilos ya esperan el siguiente tick,
+            // así que el estado no cambia mientras se copia
+            let pedida = *compartido.instantanea_pedida.lock().unwrap();
+            if pedida.is_some_and(|t| t <= reloj.tiempo_de(tick + 1)) {
+                *compartido.instantanea_pedida.lock().unwrap() = None;
+                let instantanea = Instantanea::de(&compartido, tick + 1);
+                let ruta = instantanea.ruta_por_defecto();
+                match instantanea.guardar(&ruta) {
+                    Ok(()) => println!("Instantánea de los {:.1} s guardada en {}", instantanea.tiempo(), ruta.display()),
+                    Err(e) => eprintln!("No se pudo guardar la instantánea en {}: {}", ruta.display(), e),
                 }
             }
         }
-    });
+
+        // Lo que quedó en el búfer desde el último evento
+        if let Some(Err(e)) = registro.as_mut().map(Registro::terminar) {
+            eprintln!("No se pudo escribir el registro: {}", e);
+        }
+    })
+}
+
+// Cambios de carril según MOBIL: un vehículo se pasa a un carril vecino si ahí
+// acelera más, sin obligar al que queda detrás a frenar fuerte y descontando lo que
+// pierden los que quedan detrás en ambos carriles. Se prefiere la acera y, cerca
+// del cruce, el carril desde el que se gira. No se cambia ya dentro de la zona de
+// la intersección, detenido, orillado ni mientras termina otro cambio.
+fn cambiar_carriles(carros: &mut [Carro], red: &Red) {
+    // Se arma una vez por tick y se corrige con cada cambio, así buscar los
+    // vecinos no obliga a recorrer todos los vehículos
+    let mut por_linea = ordenar_por_linea(carros, red);
+
+    for i in 0..carros.len() {
+        let carro = carros[i];
+        let enlace = &red.enlaces[carro.enlace];
+        if enlace.carriles < 2 || carro.recorrido_giro.is_some() || carro.orillado || carro.siniestro.is_some()
+            || carro.velocidad < VELOCIDAD_DETENIDO {
+            continue;
+        }
+        let lateral = if carro.direccion.eje_horizontal() { carro.posicion[1] } else { carro.posicion[0] };
+        if (red.lateral_carril(carro.enlace, carro.carril) - lateral).abs() >= 0.01 {
+            continue;
+        }
+        let frente = carro.direccion.avance(carro.posicion) + carro.tipo.dimensiones()[0] / 2.0;
+        let hasta_el_cruce = red.entrada_caja(carro.enlace) - frente;
+        if hasta_el_cruce < ANCHO_CARRIL * AVANCE_CAMBIO {
+            continue;
+        }
+
+        let para_girar = red.carril_para(carro.enlace, carro.movimiento)
+            .filter(|_| hasta_el_cruce < DISTANCIA_PREPARAR_GIRO);
+        let sesgo = |carril: usize| {
+            let mut sesgo = if carril > carro.carril { SESGO_DERECHA } else { -SESGO_DERECHA };
+            if let Some(objetivo) = para_girar {
+                sesgo += if carril.abs_diff(objetivo) < carro.carril.abs_diff(objetivo) { SESGO_GIRO } else { -SESGO_GIRO };
+            }
+            sesgo
+        };
+
+        let mejor = [carro.carril.wrapping_sub(1), carro.carril + 1].into_iter()
+            .filter(|&carril| carril < enlace.carriles)
+            .filter_map(|carril| ganancia_cambio(carros, &por_linea, i, enlace.lineas[carril], red).map(|g| (carril, g + sesgo(carril))))
+            .filter(|&(_, ganancia)| ganancia > UMBRAL_CAMBIO_CARRIL)
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((carril, _)) = mejor {
+            por_linea[red.linea_de(&carros[i])].retain(|&k| k != i);
+            carros[i].carril = carril;
+            let orden = &mut por_linea[red.linea_de(&carros[i])];
+            let posicion = orden.partition_point(|&k| orden_en_linea(carros, k, i).is_lt());
+            orden.insert(posicion, i);
+        }
+    }
+}
+
+// Índices de los vehículos de cada línea, del último al más adelantado
+fn ordenar_por_linea(carros: &[Carro], red: &Red) -> Vec<Vec<usize>> {
+    let mut por_linea = vec![Vec::new(); red.num_lineas];
+    for (k, carro) in carros.iter().enumerate() {
+        por_linea[red.linea_de(carro)].push(k);
+    }
+    for orden in &mut por_linea {
+        orden.sort_by(|&a, &b| orden_en_linea(carros, a, b));
+    }
+    por_linea
+}
+
+// Orden de dos vehículos de la misma línea según su avance; a igual avance, por índice
+fn orden_en_linea(carros: &[Carro], a: usize, b: usize) -> std::cmp::Ordering {
+    let avance = |k: usize| carros[k].direccion.avance(carros[k].posicion);
+    avance(a).total_cmp(&avance(b)).then(a.cmp(&b))
+}
+
+// Lo que gana el vehículo `i` al pasarse a la línea, con la cortesía descontada,
+// o None si no cabe o si el que le quedaría detrás tendría que frenar de más
+fn ganancia_cambio(carros: &[Carro], por_linea: &[Vec<usize>], i: usize, linea_nueva: usize, red: &Red) -> Option<f32> {
+    let avance = |k: usize| carros[k].direccion.avance(carros[k].posicion);
+    let medio_largo = |k: usize| carros[k].tipo.dimensiones()[0] / 2.0;
+    let hueco = |atras: usize, adelante: usize| (avance(adelante) - medio_largo(adelante)) - (avance(atras) + medio_largo(atras));
+    let aceleracion = |k: usize, lider: Option<usize>| {
+        aceleracion_idm(&carros[k], lider.map(|l| (hueco(k, l), carros[l].velocidad)))
+    };
+    // El más cercano adelante y el más cercano detrás de `i` en una línea
+    let vecinos = |linea: usize| {
+        let orden = &por_linea[linea];
+        let hasta = orden.partition_point(|&k| avance(k) <= avance(i));
+        let adelante = orden.get(hasta).copied();
+        let atras = orden[..hasta].iter().rev().copied().find(|&k| k != i);
+        (adelante, atras)
+    };
+
+    let (lider_nuevo, seguidor_nuevo) = vecinos(linea_nueva);
+    if lider_nuevo.is_some_and(|l| hueco(i, l) < DISTANCIA_MINIMA)
+        || seguidor_nuevo.is_some_and(|s| hueco(s, i) < DISTANCIA_MINIMA) {
+        return None;
+    }
+    let nuevo = match seguidor_nuevo {
+        Some(s) => {
+            let despues = aceleracion(s, Some(i));
+            if despues < -carros[s].tipo.frenado_comodo() {
+                return None;
+            }
+            despues - aceleracion(s, lider_nuevo)
+        }
+        None => 0.0,
+    };
+
+    let (lider_actual, seguidor_actual) = vecinos(red.linea_de(&carros[i]));
+    let propia = aceleracion(i, lider_nuevo) - aceleracion(i, lider_actual);
+    let viejo = seguidor_actual.map_or(0.0, |s| aceleracion(s, lider_actual) - aceleracion(s, Some(i)));
+    Some(propia + CORTESIA * (nuevo + viejo))
+}
+
+// Distancia hasta donde el vehículo tiene que esperar por un paso de peatones
+// ocupado que está en su camino. Si el paso queda después de la intersección,
+// espera en la entrada para no bloquearla. Solo cuentan los pasos de los dos
+// nodos del enlace por el que va.
+fn distancia_a_peatones(carro: &Carro, frente: f32, pasos_ocupados: &[&PasoPeatonal], red: &Red) -> Option<f32> {
+    let direccion = carro.direccion;
+    let enlace = &red.enlaces[carro.enlace];
+    let cruce = red.fin(carro.enlace);
+    let centro = direccion.avance(cruce);
+    let entrada = red.entrada_caja(carro.enlace);
+
+    if let Some(recorrido) = carro.recorrido_giro {
+        // Girando: solo importa el paso del brazo por el que sale
+        let salida = direccion.salida(carro.movimiento);
+        let curva = curva_de(carro, red)?;
+        let (fin, _) = curva.punto(curva.largo());
+        return pasos_ocupados.iter()
+            .filter(|paso| paso.nodo == enlace.hasta && paso.cruza(salida))
+            .map(|paso| paso.tramo(salida).0 - salida.avance(fin))
+            .filter(|&despues| despues >= 0.0)
+            .map(|despues| curva.largo() - recorrido + despues - carro.tipo.dimensiones()[0] / 2.0)
+            .min_by(f32::total_cmp);
+    }
+
+    let mut distancias = Vec::new();
+    for paso in pasos_ocupados.iter().filter(|paso| paso.nodo == enlace.hasta || paso.nodo == enlace.desde) {
+        if paso.cruza(direccion) {
+            let (inicio, _) = paso.tramo(direccion);
+            let despues_de_la_caja = inicio > centro;
+            // Los que giran no pasan por el paso del otro lado
+            if (despues_de_la_caja && carro.movimiento != Movimiento::Recto) || frente > inicio {
+                continue;
+            }
+            if despues_de_la_caja && frente <= entrada {
+                distancias.push(entrada - frente);
+            } else {
+                distancias.push(inicio - frente);
+            }
+        }
+
+        // Antes de empezar a girar se espera si el paso de salida está ocupado
+        if carro.movimiento != Movimiento::Recto && frente <= entrada {
+            let salida = direccion.salida(carro.movimiento);
+            if paso.nodo == enlace.hasta && paso.cruza(salida) && paso.tramo(salida).0 > salida.avance(cruce) {
+                distancias.push(entrada - frente);
+            }
+        }
+    }
+    distancias.into_iter().min_by(f32::total_cmp)
+}
+
+// Distancia hasta el vehículo chocado más cercano que ocupa el camino del vehículo.
+// Yendo recto se mira la franja de su ancho hacia adelante, que para los que van a
+// girar termina al otro lado del cruce; girando, si un poco más adelante en la
+// curva ya tocaría a alguno.
+fn distancia_a_siniestros(carro: &Carro, frente: f32, siniestros: &[Rectangulo], red: &Red) -> Option<f32> {
+    if siniestros.is_empty() {
+        return None;
+    }
+    if let Some(recorrido) = carro.recorrido_giro {
+        let curva = curva_de(carro, red)?;
+        let (posicion, angulo) = curva.punto(recorrido + DISTANCIA_MINIMA);
+        let adelante = Rectangulo::de(&Carro { posicion, angulo, ..*carro });
+        return siniestros.iter().any(|s| s.toca(&adelante)).then_some(0.0);
+    }
+
+    let direccion = carro.direccion;
+    let horizontal = direccion.eje_horizontal();
+    let lateral = |p: [f32; 2]| if horizontal { p[1] } else { p[0] };
+    let mi_avance = direccion.avance(carro.posicion);
+    let medio_ancho = carro.tipo.dimensiones()[1] / 2.0 + 2.0;
+    let (izquierda, derecha) = (lateral(carro.posicion) - medio_ancho, lateral(carro.posicion) + medio_ancho);
+    let limite = if carro.movimiento == Movimiento::Recto {
+        f32::INFINITY
+    } else {
+        let nodo = &red.nodos[red.enlaces[carro.enlace].hasta];
+        direccion.avance(nodo.posicion) + nodo.media_caja
+    };
+
+    siniestros.iter()
+        .filter_map(|s| {
+            let esquinas = s.esquinas();
+            let inicio = esquinas.iter().map(|&p| direccion.avance(p)).fold(f32::INFINITY, f32::min);
+            let laterales = esquinas.map(lateral);
+            let desde = laterales.iter().copied().fold(f32::INFINITY, f32::min);
+            let hasta = laterales.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            let en_franja = hasta >= izquierda && desde <= derecha;
+            (en_franja && inicio > mi_avance && inicio <= limite).then(|| (inicio - frente).max(0.0))
+        })
+        .min_by(f32::total_cmp)
+}
+
+// Si el peatón queda dentro del rectángulo del vehículo (con su radio de margen)
+fn toca_peaton(carro: &Carro, punto: [f32; 2]) -> bool {
+    let [largo, ancho] = carro.tipo.dimensiones();
+    let (sin, cos) = carro.angulo.sin_cos();
+    let dx = punto[0] - carro.posicion[0];
+    let dy = punto[1] - carro.posicion[1];
+    // Coordenadas del peatón en el sistema del vehículo
+    let adelante = dx * cos + dy * sin;
+    let costado = -dx * sin + dy * cos;
+    adelante.abs() <= largo / 2.0 + RADIO_PEATON && costado.abs() <= ancho / 2.0 + RADIO_PEATON
+}
+
+// Aceleración según el Intelligent Driver Model. `obstaculo` es el hueco hasta lo
+// que hay adelante y su velocidad, o None si el carril está libre.
+fn aceleracion_idm(carro: &Carro, obstaculo: Option<(f32, f32)>) -> f32 {
+    let maxima = carro.tipo.aceleracion_maxima();
+    let frenado = carro.tipo.frenado_comodo();
+    let v = carro.velocidad;
+
+    let libre = 1.0 - (v / carro.velocidad_deseada).powi(EXPONENTE_ACELERACION);
+
+    let interaccion = match obstaculo {
+        Some((hueco, velocidad_lider)) => {
+            let diferencia = v - velocidad_lider;
+            let deseado = DISTANCIA_MINIMA
+                + (v * TIEMPO_SEGUIMIENTO + v * diferencia / (2.0 * (maxima * frenado).sqrt())).max(0.0);
+            (deseado / hueco.max(0.1)).powi(2)
+        }
+        None => 0.0,
+    };
+
+    maxima * (libre - interaccion)
+}
+
+// Si vale la pena frenar ante el semáforo. En amarillo solo se frena si se puede
+// hacer cómodamente; en rojo se acepta frenar más fuerte, pero si ya no alcanza
+// (entró en amarillo y sigue antes de la línea) se termina de pasar.
+fn puede_detenerse(carro: &Carro, distancia: f32, estado: EstadoSemaforo) -> bool {
+    let frenado = match estado {
+        EstadoSemaforo::Rojo => carro.tipo.frenado_comodo() * FACTOR_FRENADO_MAXIMO,
+        _ => carro.tipo.frenado_comodo(),
+    };
+    carro.velocidad * carro.velocidad <= 2.0 * frenado * distancia
+}
+
+// Mueve un vehículo por su carril o, si gira, por la curva dentro de la intersección
+// al final de su enlace. Al pasar por un cruce sigue por el enlace siguiente de su camino.
+fn mover_carro(carro: &mut Carro, mut distancia: f32, red: &Red) {
+    let cruce = red.fin(carro.enlace);
+
+    // ¿Llega en este paso a la entrada de la intersección?
+    if carro.recorrido_giro.is_none() && carro.movimiento != Movimiento::Recto {
+        let entrada = red.entrada_caja(carro.enlace);
+        let faltante = entrada - carro.direccion.avance(carro.posicion);
+        if (0.0..=distancia).contains(&faltante) {
+            desplazar(carro, faltante);
+            distancia -= faltante;
+            carro.recorrido_giro = Some(0.0);
+        }
+    }
+
+    let Some(recorrido) = carro.recorrido_giro else {
+        desplazar(carro, distancia);
+        acercar_al_carril(carro, red, distancia);
+        // Siguiendo recto, cambia de enlace al pasar por el centro del cruce
+        let paso_el_centro = carro.direccion.avance(carro.posicion) >= carro.direccion.avance(cruce);
+        if let Some(siguiente) = red.siguiente_enlace(carro.enlace, carro.destino).filter(|_| paso_el_centro) {
+            entrar_enlace(carro, red, siguiente);
+        }
+        return;
+    };
+
+    // Curva::new solo devuelve None para Movimiento::Recto, que nunca tiene recorrido_giro
+    let curva = curva_de(carro, red).unwrap();
+    let recorrido = recorrido + distancia;
+
+    if recorrido < curva.largo() {
+        let (posicion, angulo) = curva.punto(recorrido);
+        carro.posicion = posicion;
+        carro.angulo = angulo;
+        carro.recorrido_giro = Some(recorrido);
+    } else {
+        // Terminó la curva: sigue recto por el carril de salida
+        let (salida, _) = curva.punto(curva.largo());
+        // maniobra() solo indica un giro si el enlace de salida existe
+        let siguiente = red.enlace_tras(carro.enlace, carro.movimiento).unwrap();
+        entrar_enlace(carro, red, siguiente);
+        carro.recorrido_giro = None;
+        carro.angulo = carro.direccion.angulo();
+        carro.posicion = salida;
+        desplazar(carro, recorrido - curva.largo());
+    }
+}
+
+// Curva del giro que hace el vehículo al final de su enlace, desde su carril
+// hasta el del mismo número en la vía de salida (o su último carril)
+fn curva_de(carro: &Carro, red: &Red) -> Option<Curva> {
+    let nodo = &red.nodos[red.enlaces[carro.enlace].hasta];
+    let salida = red.enlace_tras(carro.enlace, carro.movimiento)?;
+    let carril_salida = carro.carril.min(red.enlaces[salida].carriles - 1);
+    let desplazamientos = [desplazamiento_carril(carro.carril), desplazamiento_carril(carril_salida)];
+    Curva::new(nodo.posicion, nodo.media_caja, desplazamientos, carro.direccion, carro.movimiento)
+}
+
+// Pasa el vehículo al enlace y decide qué hará al final de este. Sigue en el
+// carril del mismo número, o en el último si la vía nueva tiene menos.
+fn entrar_enlace(carro: &mut Carro, red: &Red, enlace: usize) {
+    carro.enlace = enlace;
+    carro.carril = carro.carril.min(red.enlaces[enlace].carriles - 1);
+    carro.direccion = red.enlaces[enlace].direccion;
+    carro.movimiento = red.maniobra(enlace, carro.destino);
+}
+
+// Corre el vehículo hacia el centro de su carril después de avanzar `distancia`,
+// inclinado mientras se desliza. Sin avanzar no se mueve de costado.
+fn acercar_al_carril(carro: &mut Carro, red: &Red, distancia: f32) {
+    let horizontal = carro.direccion.eje_horizontal();
+    let lateral = if horizontal { 1 } else { 0 };
+    let falta = red.lateral_carril(carro.enlace, carro.carril) - carro.posicion[lateral];
+    if falta.abs() < 0.01 || distancia <= 0.0 {
+        return;
+    }
+
+    let maximo = distancia / AVANCE_CAMBIO;
+    let paso = falta.clamp(-maximo, maximo);
+    carro.posicion[lateral] += paso;
+    carro.angulo = if (falta - paso).abs() < 0.01 {
+        carro.direccion.angulo()
+    } else {
+        let [x, y] = carro.direccion.vector();
+        let costado = paso.signum();
+        if horizontal {
+            costado.atan2(x * AVANCE_CAMBIO)
+        } else {
+            (y * AVANCE_CAMBIO).atan2(costado)
+        }
+    };
+}
+
+fn desplazar(carro: &mut Carro, distancia: f32) {
+    let [x, y] = carro.direccion.vector();
+    carro.posicion[0] += x * distancia;
+    carro.posicion[1] += y * distancia;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::escenario::{Fase, ParametrosAdaptativo};
+
+    // Dos fases (este-oeste y norte-sur) con 4 s de entreverde cada una: 8 s perdidos por ciclo
+    fn escenario() -> Escenario {
+        Escenario {
+            duracion_amarillo: 3.0,
+            duracion_todo_rojo: 1.0,
+            fases: vec![
+                Fase::new(&[Direccion::Este, Direccion::Oeste]),
+                Fase::new(&[Direccion::Norte, Direccion::Sur]),
+            ],
+            adaptativo: ParametrosAdaptativo {
+                ciclo_minimo: 30.0,
+                ciclo_maximo: 120.0,
+                verde_minimo: 5.0,
+                flujo_saturacion: 0.5,
+                ..ParametrosAdaptativo::default()
+            },
+            ..Escenario::default()
+        }
+    }
+
+    // Demanda en veh/s indexada por dirección: [este, norte, oeste, sur]
+    fn demanda(este: f32, norte: f32, oeste: f32, sur: f32) -> [f32; 4] {
+        [este, norte, oeste, sur]
+    }
+
+    fn parecido(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn ciclo_de_webster_y_reparto_proporcional() {
+        // Razones 0.6 y 0.2: ciclo = (1.5 * 8 + 5) / (1 - 0.8) = 85 s, con 77 s de verde
+        let verdes = plan_webster(&escenario(), &demanda(0.3, 0.1, 0.0, 0.05));
+        assert!(parecido(verdes[0], 77.0 * 0.75), "{:?}", verdes);
+        assert!(parecido(verdes[1], 77.0 * 0.25), "{:?}", verdes);
+        assert!(parecido(verdes.iter().sum::<f32>() + 8.0, 85.0));
+    }
+
+    #[test]
+    fn cada_fase_usa_su_direccion_mas_cargada() {
+        let a = plan_webster(&escenario(), &demanda(0.3, 0.1, 0.0, 0.0));
+        let b = plan_webster(&escenario(), &demanda(0.1, 0.0, 0.3, 0.1));
+        assert!(a.iter().zip(&b).all(|(x, y)| parecido(*x, *y)), "{:?} {:?}", a, b);
+    }
+
+    #[test]
+    fn poca_demanda_da_el_ciclo_minimo() {
+        // Razones 0.2 y 0.2: Webster da 28.3 s, por debajo de los 30 s
+        let verdes = plan_webster(&escenario(), &demanda(0.1, 0.1, 0.1, 0.1));
+        assert!(parecido(verdes[0], 11.0) && parecido(verdes[1], 11.0), "{:?}", verdes);
+    }
+
+    #[test]
+    fn sin_demanda_reparte_por_igual() {
+        let verdes = plan_webster(&escenario(), &demanda(0.0, 0.0, 0.0, 0.0));
+        assert!(parecido(verdes[0], 11.0) && parecido(verdes[1], 11.0), "{:?}", verdes);
+    }
+
+    #[test]
+    fn saturado_da_el_ciclo_maximo() {
+        let verdes = plan_webster(&escenario(), &demanda(0.5, 0.5, 0.5, 0.5));
+        assert!(parecido(verdes[0], 56.0) && parecido(verdes[1], 56.0), "{:?}", verdes);
+    }
+
+    #[test]
+    fn ninguna_fase_baja_del_verde_minimo() {
+        let verdes = plan_webster(&escenario(), &demanda(0.3, 0.005, 0.0, 0.0));
+        assert!(parecido(verdes[1], 5.0), "{:?}", verdes);
+        assert!(verdes[0] > 30.0, "{:?}", verdes);
+    }
+
+    #[test]
+    fn el_ciclo_minimo_alcanza_para_los_verdes_minimos() {
+        // 8 s perdidos + 2 × 20 s de verde mínimo = 48 s, aunque ciclo_minimo diga 30
+        let mut escenario = escenario();
+        escenario.adaptativo.verde_minimo = 20.0;
+        let verdes = plan_webster(&escenario, &demanda(0.0, 0.0, 0.0, 0.0));
+        assert!(parecido(verdes[0], 20.0) && parecido(verdes[1], 20.0), "{:?}", verdes);
+    }
+
+    // Avenida de tres carriles por sentido; devuelve el enlace que entra hacia el este
+    fn avenida() -> (Red, usize) {
+        let mut escenario = Escenario::default();
+        for via in &mut escenario.red.vias {
+            via.carriles = 3;
+        }
+        let red = Red::new(&escenario.red);
+        let enlace = *red.entradas.iter().find(|&&e| red.enlaces[e].direccion == Direccion::Este).unwrap();
+        (red, enlace)
+    }
+
+    // Automóvil en el carril, `distancia` px después del inicio del enlace
+    fn carro_en(red: &Red, enlace: usize, carril: usize, distancia: f32, velocidad: f32) -> Carro {
+        let direccion = red.enlaces[enlace].direccion;
+        let [x, y] = red.inicio_carril(enlace, carril);
+        let [dx, dy] = direccion.vector();
+        Carro {
+            id: 0,
+            aparicion: 0.0,
+            salida: None,
+            origen: enlace,
+            posicion: [x + dx * distancia, y + dy * distancia],
+            angulo: direccion.angulo(),
+            direccion,
+            enlace,
+            carril,
+            destino: red.enlaces[enlace].hasta,
+            movimiento: Movimiento::Recto,
+            recorrido_giro: None,
+            color: Color::WHITE,
+            velocidad,
+            velocidad_deseada: 40.0,
+            tipo: TipoVehiculo::Automovil,
+            loco: false,
+            orillado: false,
+            siniestro: None,
+            demora: 0.0,
+            paradas: 0,
+            detenido: false,
+        }
+    }
+
+    #[test]
+    fn el_cambio_mira_los_vecinos_del_carril_de_destino() {
+        let (red, enlace) = avenida();
+        let linea = |carril: usize| red.enlaces[enlace].lineas[carril];
+
+        // Detenido a 5 px adelante en el carril 0: no cabe ahí, pero sí en el 2
+        let carros = [carro_en(&red, enlace, 1, 100.0, 30.0), carro_en(&red, enlace, 0, 135.0, 0.0)];
+        let por_linea = ordenar_por_linea(&carros, &red);
+        assert!(ganancia_cambio(&carros, &por_linea, 0, linea(0), &red).is_none());
+        assert!(ganancia_cambio(&carros, &por_linea, 0, linea(2), &red).is_some());
+
+        // Uno rápido que viene 10 px atrás por el carril 2 tendría que frenar de más
+        let carros = [carro_en(&red, enlace, 1, 100.0, 30.0), carro_en(&red, enlace, 2, 60.0, 40.0)];
+        let por_linea = ordenar_por_linea(&carros, &red);
+        assert!(ganancia_cambio(&carros, &por_linea, 0, linea(2), &red).is_none());
+        assert!(ganancia_cambio(&carros, &por_linea, 0, linea(0), &red).is_some());
+    }
+
+    #[test]
+    fn conviene_el_carril_sin_lider() {
+        let (red, enlace) = avenida();
+        let linea = |carril: usize| red.enlaces[enlace].lineas[carril];
+
+        // Detenidos 30 px adelante en el carril propio y en el 0; el 2 está libre
+        let mut carros = [
+            carro_en(&red, enlace, 1, 100.0, 30.0),
+            carro_en(&red, enlace, 1, 160.0, 0.0),
+            carro_en(&red, enlace, 0, 160.0, 0.0),
+        ];
+        let por_linea = ordenar_por_linea(&carros, &red);
+        let hacia_0 = ganancia_cambio(&carros, &por_linea, 0, linea(0), &red).unwrap();
+        let hacia_2 = ganancia_cambio(&carros, &por_linea, 0, linea(2), &red).unwrap();
+        assert!(hacia_0.abs() < 1e-3, "{}", hacia_0);
+        assert!(hacia_2 > UMBRAL_CAMBIO_CARRIL, "{}", hacia_2);
+
+        cambiar_carriles(&mut carros, &red);
+        assert_eq!(carros.map(|c| c.carril), [2, 1, 0]);
+    }
 }
\ No newline at end of file
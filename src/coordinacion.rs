@@ -0,0 +1,269 @@
+// coordinacion.rs
+// Onda verde: los cruces con control fijo usan un mismo ciclo y cada uno lo
+// empieza con un desfase respecto al reloj de la simulación, para que los
+// pelotones que salen de un semáforo lleguen al siguiente en verde.
+use crate::escenario::Escenario;
+use crate::red::{direccion_entre, Red};
+
+// Plan de ciclo fijo de un cruce coordinado
+#[derive(Clone, Debug)]
+pub struct PlanCoordinado {
+    pub ciclo: f32,
+    pub desfase: f32,     // s desde el inicio de la simulación hasta que empieza la fase 0
+    pub verdes: Vec<f32>, // Verde de cada fase, ajustado para completar el ciclo
+    entreverdes: Vec<f32>,
+}
+
+impl PlanCoordinado {
+    pub fn new(escenario: &Escenario, ciclo: f32, desfase: f32) -> Self {
+        let fases = 0..escenario.fases.len();
+        let entreverdes: Vec<f32> = fases.clone().map(|i| escenario.entreverde(i)).collect();
+        let perdido: f32 = entreverdes.iter().sum();
+        let nominal: f32 = fases.clone().map(|i| escenario.verde(i)).sum();
+        // El verde que deja el ciclo se reparte en proporción a los verdes del escenario
+        let verdes = fases.map(|i| escenario.verde(i) * (ciclo - perdido) / nominal).collect();
+        Self { ciclo, desfase: desfase.rem_euclid(ciclo), verdes, entreverdes }
+    }
+
+    // Momento del ciclo en que empieza el verde de la fase
+    pub fn inicio_verde(&self, fase: usize) -> f32 {
+        (0..fase).map(|i| self.verdes[i] + self.entreverdes[i]).sum()
+    }
+
+    // Tiempo en que termina el verde de la fase que empezó en `inicio`. Se toma el
+    // fin del plan más cercano al de un verde normal, así después del arranque o
+    // de una emergencia el cruce vuelve solo a su desfase.
+    pub fn fin_verde(&self, fase: usize, inicio: f32) -> f32 {
+        let fin_en_ciclo = self.desfase + self.inicio_verde(fase) + self.verdes[fase];
+        let vueltas = ((inicio + self.verdes[fase] - fin_en_ciclo) / self.ciclo).round();
+        let fin = fin_en_ciclo + vueltas * self.ciclo;
+        if fin > inicio { fin } else { fin + self.ciclo }
+    }
+
+    // Si la fase está en verde en el instante `t`, siguiendo el plan sin interrupciones
+    pub fn en_verde(&self, fase: usize, t: f32) -> bool {
+        (t - self.desfase - self.inicio_verde(fase)).rem_euclid(self.ciclo) < self.verdes[fase]
+    }
+}
+
+// Plan de cada nodo de la red; None si no está coordinado. Los cruces del
+// corredor se desfasan lo que tarda un vehículo en llegar desde el primero a
+// la velocidad de la onda; los demás empiezan con el reloj, salvo que el
+// escenario indique su desfase.
+pub fn planes(escenario: &Escenario, red: &Red) -> Vec<Option<PlanCoordinado>> {
+    let Some(coordinacion) = &escenario.coordinacion else {
+        return vec![None; red.nodos.len()];
+    };
+    let viajes = tiempos_de_viaje(escenario, red);
+
+    (0..red.nodos.len())
+        .map(|nodo| {
+            if !red.nodos[nodo].semaforo {
+                return None;
+            }
+            let calculado = coordinacion.corredor.iter()
+                .position(|&n| n == nodo)
+                .map_or(0.0, |i| viajes[i]);
+            let desfase = escenario.red.nodos[nodo].desfase.unwrap_or(calculado);
+            Some(PlanCoordinado::new(escenario, coordinacion.ciclo, desfase))
+        })
+        .collect()
+}
+
+// Segundos desde el primer cruce del corredor hasta cada uno, a la velocidad de la onda
+fn tiempos_de_viaje(escenario: &Escenario, red: &Red) -> Vec<f32> {
+    let Some(coordinacion) = &escenario.coordinacion else {
+        return Vec::new();
+    };
+    let velocidad = coordinacion.velocidad.unwrap_or(escenario.velocidad_vehiculo);
+    let posicion = |i: usize| red.nodos[coordinacion.corredor[i]].posicion;
+
+    let mut distancia = 0.0;
+    (0..coordinacion.corredor.len())
+        .map(|i| {
+            if i > 0 {
+                let [a, b] = [posicion(i - 1), posicion(i)];
+                distancia += (b[0] - a[0]).abs() + (b[1] - a[1]).abs();
+            }
+            distancia / velocidad
+        })
+        .collect()
+}
+
+// Diagrama espacio-tiempo del corredor en texto: una fila por cruce (el primero
+// abajo) y dos ciclos de ancho. `#` es verde dentro de la banda que recorre todo
+// el corredor sin detenerse, `=` verde fuera de ella y `.` amarillo o rojo.
+pub fn diagrama_bandas(escenario: &Escenario, red: &Red) -> Result<String, String> {
+    let coordinacion = escenario.coordinacion.as_ref()
+        .ok_or("El escenario no tiene [coordinacion]")?;
+    let corredor = &coordinacion.corredor;
+    if corredor.is_empty() {
+        return Err("La coordinación no tiene corredor".to_string());
+    }
+
+    // validar() garantiza que el corredor va en línea recta y que sus nodos tienen plan
+    let direccion = direccion_entre(red.nodos[corredor[0]].posicion, red.nodos[corredor[1]].posicion).unwrap();
+    let fase = escenario.fases.iter().position(|f| f.direcciones.contains(&direccion))
+        .ok_or(format!("Ninguna fase da verde a la dirección \"{}\"", direccion))?;
+    let planes = planes(escenario, red);
+    let planes: Vec<&PlanCoordinado> = corredor.iter().map(|&n| planes[n].as_ref().unwrap()).collect();
+    let viajes = tiempos_de_viaje(escenario, red);
+
+    // Sale del primer cruce en `salida` y pasa por todos los demás en verde
+    let en_banda = |salida: f32| (0..corredor.len()).all(|i| planes[i].en_verde(fase, salida + viajes[i]));
+
+    let ciclo = coordinacion.ciclo;
+    let columnas = 72;
+    let resolucion = 2.0 * ciclo / columnas as f32;
+    let mut texto = format!("Corredor hacia el {} (fase {}), ciclo de {} s\n", direccion, fase, ciclo);
+
+    for i in (0..corredor.len()).rev() {
+        let fila: String = (0..columnas)
+            .map(|columna| {
+                let t = (columna as f32 + 0.5) * resolucion;
+                if !planes[i].en_verde(fase, t) {
+                    '.'
+                } else if en_banda(t - viajes[i]) {
+                    '#'
+                } else {
+                    '='
+                }
+            })
+            .collect();
+        texto += &format!("nodo {:>3} {:>6.1} s |{}|\n", corredor[i], planes[i].desfase, fila);
+    }
+    // Eje de tiempo alineado con las columnas
+    texto += &format!("{:18}0 s{:>ancho$.0} s\n", "", 2.0 * ciclo, ancho = columnas - 5);
+
+    // Ancho de la banda: salidas del primer cruce que pasan todo el corredor, muestreando cada 0.1 s
+    let muestras = (ciclo * 10.0).ceil() as usize;
+    let en_la_banda = (0..muestras).filter(|&m| en_banda(m as f32 / 10.0)).count();
+    texto += &format!(
+        "Banda: {:.1} s de {:.1} s de verde a {} px/s\n",
+        en_la_banda as f32 / 10.0,
+        planes[0].verdes[fase],
+        coordinacion.velocidad.unwrap_or(escenario.velocidad_vehiculo),
+    );
+    Ok(texto)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Avenida con tres cruces a 250 px uno de otro; la onda va a 40 px/s, así que
+    // tarda 6.25 s de un cruce al siguiente. Dos fases de 10 s de verde y 4 s de
+    // entreverde.
+    const CORREDOR: &str = r#"
+        duracion_verde = 10.0
+        duracion_amarillo = 3.0
+        duracion_todo_rojo = 1.0
+
+        [coordinacion]
+        ciclo = 30.0
+        velocidad = 40.0
+        corredor = [0, 1, 2]
+
+        [red]
+        nodos = [
+            { posicion = [150.0, 325.0] },
+            { posicion = [400.0, 325.0] },
+            { posicion = [650.0, 325.0] },
+            { posicion = [0.0, 325.0] },
+            { posicion = [800.0, 325.0] },
+            { posicion = [150.0, 0.0] },
+            { posicion = [150.0, 650.0] },
+            { posicion = [400.0, 0.0] },
+            { posicion = [400.0, 650.0] },
+            { posicion = [650.0, 0.0] },
+            { posicion = [650.0, 650.0] },
+        ]
+        vias = [
+            { nodos = [3, 0] }, { nodos = [0, 1] }, { nodos = [1, 2] }, { nodos = [2, 4] },
+            { nodos = [5, 0] }, { nodos = [0, 6] }, { nodos = [7, 1] }, { nodos = [1, 8] },
+            { nodos = [9, 2] }, { nodos = [2, 10] },
+        ]
+    "#;
+
+    fn corredor() -> (Escenario, Red) {
+        let escenario = Escenario::desde_toml(CORREDOR).unwrap();
+        let red = Red::new(&escenario.red);
+        (escenario, red)
+    }
+
+    fn plan(desfase: f32) -> PlanCoordinado {
+        PlanCoordinado::new(&corredor().0, 30.0, desfase)
+    }
+
+    fn parecido(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn el_verde_que_sobra_se_reparte_entre_las_fases() {
+        // 30 s de ciclo menos 8 s de entreverdes: 11 s para cada fase
+        let plan = plan(0.0);
+        assert!(parecido(plan.verdes[0], 11.0) && parecido(plan.verdes[1], 11.0), "{:?}", plan.verdes);
+        assert!(parecido(plan.inicio_verde(0), 0.0));
+        assert!(parecido(plan.inicio_verde(1), 15.0));
+    }
+
+    #[test]
+    fn el_desfase_queda_dentro_del_ciclo() {
+        assert!(parecido(plan(65.0).desfase, 5.0));
+        assert!(parecido(plan(-5.0).desfase, 25.0));
+    }
+
+    #[test]
+    fn en_verde_sigue_el_desfase() {
+        let plan = plan(5.0);
+        assert!(!plan.en_verde(0, 4.9));
+        assert!(plan.en_verde(0, 5.0) && plan.en_verde(0, 15.9));
+        assert!(!plan.en_verde(0, 16.1));
+        assert!(plan.en_verde(0, 35.5));
+        assert!(plan.en_verde(1, 20.5) && !plan.en_verde(1, 31.5));
+    }
+
+    #[test]
+    fn fin_verde_vuelve_al_desfase() {
+        let plan = plan(5.0);
+        // A tiempo, tarde o fuera de plan: termina en el fin del plan más cercano
+        assert!(parecido(plan.fin_verde(0, 5.0), 16.0));
+        assert!(parecido(plan.fin_verde(0, 8.0), 16.0));
+        assert!(parecido(plan.fin_verde(0, 14.0), 16.0));
+        assert!(parecido(plan.fin_verde(0, 30.0), 46.0));
+        // Nunca antes de haber empezado
+        assert!(parecido(plan.fin_verde(0, 16.0), 46.0));
+    }
+
+    #[test]
+    fn onda_verde_a_lo_largo_del_corredor() {
+        let (escenario, red) = corredor();
+        let planes = planes(&escenario, &red);
+        let desfases: Vec<f32> = (0..3).map(|n| planes[n].as_ref().unwrap().desfase).collect();
+        assert!(parecido(desfases[0], 0.0) && parecido(desfases[1], 6.25) && parecido(desfases[2], 12.5), "{:?}", desfases);
+        // Los extremos no tienen semáforo
+        assert!(planes[3..].iter().all(Option::is_none));
+
+        // Quien sale del primer cruce con verde llega a los demás con verde
+        for salida in [0.0, 5.0, 10.5, 30.0] {
+            assert!((0..3).all(|i| planes[i].as_ref().unwrap().en_verde(0, salida + 6.25 * i as f32)), "{}", salida);
+        }
+    }
+
+    #[test]
+    fn desfase_indicado_en_el_nodo() {
+        let (mut escenario, red) = corredor();
+        escenario.red.nodos[1].desfase = Some(3.0);
+        let planes = planes(&escenario, &red);
+        assert!(parecido(planes[1].as_ref().unwrap().desfase, 3.0));
+        assert!(parecido(planes[2].as_ref().unwrap().desfase, 12.5));
+    }
+
+    #[test]
+    fn sin_coordinacion_no_hay_planes() {
+        let (mut escenario, red) = corredor();
+        escenario.coordinacion = None;
+        assert!(planes(&escenario, &red).iter().all(Option::is_none));
+    }
+}
@@ -0,0 +1,185 @@
+// demanda.rs
+// Llegadas de vehículos según la [demanda] del escenario: en cada extremo de la
+// red los vehículos llegan como un proceso de Poisson, con intervalos
+// exponenciales entre uno y otro, y con un flujo que puede cambiar con la hora.
+use crate::escenario::Escenario;
+use crate::modelo::Aleatorio;
+use crate::red::Red;
+use rand::Rng;
+
+// Demanda de una entrada de la red
+#[derive(Clone, Debug)]
+pub struct Llegadas {
+    pub enlace: usize,     // Enlace que sale del extremo
+    flujo: f32,            // veh/s, antes de aplicar el perfil
+    perfil: Vec<[f32; 2]>, // [s, factor]
+    maximo: f32,           // veh/s en el punto más alto del perfil
+}
+
+impl Llegadas {
+    fn new(enlace: usize, flujo_hora: f32, perfil: &[[f32; 2]]) -> Self {
+        let flujo = flujo_hora / 3600.0;
+        // Entre puntos se interpola en línea recta: el máximo está en uno de ellos
+        let factor_maximo = perfil.iter().map(|p| p[1]).reduce(f32::max).unwrap_or(1.0);
+        Self { enlace, flujo, perfil: perfil.to_vec(), maximo: flujo * factor_maximo }
+    }
+
+    fn factor(&self, t: f32) -> f32 {
+        match self.perfil.iter().position(|p| p[0] > t) {
+            None => self.perfil.last().map_or(1.0, |p| p[1]),
+            Some(0) => self.perfil[0][1],
+            Some(i) => {
+                let ([t0, f0], [t1, f1]) = (self.perfil[i - 1], self.perfil[i]);
+                f0 + (f1 - f0) * (t - t0) / (t1 - t0)
+            }
+        }
+    }
+
+    // veh/s en el instante t
+    pub fn tasa(&self, t: f32) -> f32 {
+        self.flujo * self.factor(t)
+    }
+
+    // Instante de la próxima llegada después de `desde`. Se sortean llegadas al
+    // flujo máximo y cada una se acepta con probabilidad tasa / máximo (método de
+    // rechazo de Lewis y Shedler); con flujo constante se aceptan todas.
+    pub fn siguiente(&self, rng: &mut Aleatorio, desde: f32) -> f32 {
+        let ultimo = self.perfil.last().map_or(0.0, |p| p[0]);
+        let mut t = desde;
+        loop {
+            // Pasado el último punto el flujo ya no cambia: si es cero no llega nadie más
+            if self.maximo <= 0.0 || (t >= ultimo && self.tasa(t) <= 0.0) {
+                return f32::INFINITY;
+            }
+            // Intervalo exponencial: -ln(U) / λ, con U en (0, 1]
+            let u = 1.0 - rng.random::<f32>();
+            t -= u.ln() / self.maximo;
+            if rng.random::<f32>() * self.maximo < self.tasa(t) {
+                return t;
+            }
+        }
+    }
+}
+
+// Demanda de cada entrada de la red, en el orden de `red.entradas`; None si el
+// escenario no tiene [demanda]
+pub fn llegadas(escenario: &Escenario, red: &Red) -> Option<Vec<Llegadas>> {
+    let demanda = escenario.demanda.as_ref()?;
+    Some(red.entradas.iter()
+        .map(|&enlace| {
+            let nodo = red.enlaces[enlace].desde;
+            match demanda.accesos.iter().find(|a| a.nodo == nodo) {
+                Some(acceso) => Llegadas::new(enlace, acceso.flujo, acceso.perfil.as_ref().unwrap_or(&demanda.perfil)),
+                None => Llegadas::new(enlace, demanda.flujo, &demanda.perfil),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::escenario::{Demanda, DemandaAcceso};
+    use rand::SeedableRng;
+
+    fn parecido(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    // Llegadas desde 0 hasta `hasta` s
+    fn sortear(llegadas: &Llegadas, semilla: u64, hasta: f32) -> Vec<f32> {
+        let mut rng = Aleatorio::seed_from_u64(semilla);
+        let mut instantes = Vec::new();
+        let mut t = llegadas.siguiente(&mut rng, 0.0);
+        while t < hasta {
+            instantes.push(t);
+            t = llegadas.siguiente(&mut rng, t);
+        }
+        instantes
+    }
+
+    #[test]
+    fn el_perfil_se_interpola_entre_puntos() {
+        let llegadas = Llegadas::new(0, 3600.0, &[[100.0, 0.4], [900.0, 1.0], [1800.0, 1.6]]);
+        assert!(parecido(llegadas.factor(0.0), 0.4));   // Antes del primer punto
+        assert!(parecido(llegadas.factor(100.0), 0.4));
+        assert!(parecido(llegadas.factor(500.0), 0.7));
+        assert!(parecido(llegadas.factor(900.0), 1.0));
+        assert!(parecido(llegadas.factor(1350.0), 1.3));
+        assert!(parecido(llegadas.factor(5000.0), 1.6)); // Después del último se mantiene
+        assert!(parecido(llegadas.tasa(1350.0), 1.3));   // 3600 veh/h = 1 veh/s
+    }
+
+    #[test]
+    fn sin_perfil_el_flujo_es_constante() {
+        let llegadas = Llegadas::new(0, 1800.0, &[]);
+        assert!(parecido(llegadas.tasa(0.0), 0.5) && parecido(llegadas.tasa(10_000.0), 0.5));
+    }
+
+    #[test]
+    fn flujo_cero_no_trae_a_nadie() {
+        let mut rng = Aleatorio::seed_from_u64(7);
+        assert_eq!(Llegadas::new(0, 0.0, &[]).siguiente(&mut rng, 0.0), f32::INFINITY);
+        assert_eq!(Llegadas::new(0, 900.0, &[[0.0, 0.0]]).siguiente(&mut rng, 0.0), f32::INFINITY);
+    }
+
+    #[test]
+    fn despues_de_un_perfil_que_termina_en_cero_no_llega_nadie() {
+        let llegadas = Llegadas::new(0, 3600.0, &[[0.0, 1.0], [100.0, 1.0], [101.0, 0.0]]);
+        let instantes = sortear(&llegadas, 7, f32::INFINITY);
+        assert!(!instantes.is_empty() && instantes.iter().all(|&t| t < 101.0), "{:?}", instantes.last());
+        let mut rng = Aleatorio::seed_from_u64(7);
+        assert_eq!(llegadas.siguiente(&mut rng, 200.0), f32::INFINITY);
+    }
+
+    #[test]
+    fn nadie_llega_mientras_la_tasa_es_cero() {
+        let perfil = [[0.0, 1.0], [100.0, 1.0], [101.0, 0.0], [200.0, 0.0], [201.0, 1.0]];
+        let instantes = sortear(&Llegadas::new(0, 3600.0, &perfil), 7, 400.0);
+        assert!(instantes.iter().all(|&t| !(101.0..=200.0).contains(&t)));
+        assert!(instantes.iter().any(|&t| t > 201.0));
+    }
+
+    #[test]
+    fn la_cantidad_sigue_la_tasa_de_cada_tramo() {
+        // 0.5 veh/s los primeros 1000 s y 2 veh/s los siguientes
+        let perfil = [[0.0, 0.5], [1000.0, 0.5], [1001.0, 2.0]];
+        let instantes = sortear(&Llegadas::new(0, 3600.0, &perfil), 7, 2001.0);
+        let primeros = instantes.iter().filter(|&&t| t < 1000.0).count() as f32;
+        let segundos = instantes.iter().filter(|&&t| t >= 1001.0).count() as f32;
+        assert!((primeros - 500.0).abs() < 75.0, "{}", primeros);
+        assert!((segundos - 2000.0).abs() < 150.0, "{}", segundos);
+    }
+
+    #[test]
+    fn cada_acceso_usa_su_flujo_y_su_perfil() {
+        // Cruce por defecto: el nodo 1 es el extremo oeste y el 3 el este
+        let mut escenario = Escenario {
+            demanda: Some(Demanda {
+                flujo: 360.0,
+                perfil: vec![[0.0, 1.0], [100.0, 2.0]],
+                accesos: vec![
+                    DemandaAcceso { nodo: 1, flujo: 1800.0, perfil: None },
+                    DemandaAcceso { nodo: 3, flujo: 720.0, perfil: Some(vec![[0.0, 3.0]]) },
+                ],
+            }),
+            ..Escenario::default()
+        };
+        let red = Red::new(&escenario.red);
+        let todas = llegadas(&escenario, &red).unwrap();
+        assert_eq!(todas.len(), red.entradas.len());
+
+        for (llegada, &enlace) in todas.iter().zip(&red.entradas) {
+            assert_eq!(llegada.enlace, enlace);
+            let esperada = match red.enlaces[enlace].desde {
+                1 => 0.5 * 2.0,
+                3 => 0.2 * 3.0,
+                _ => 0.1 * 2.0,
+            };
+            assert!(parecido(llegada.tasa(100.0), esperada), "{} {}", enlace, llegada.tasa(100.0));
+        }
+
+        escenario.demanda = None;
+        assert!(llegadas(&escenario, &red).is_none());
+    }
+}
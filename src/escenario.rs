@@ -0,0 +1,677 @@
+// escenario.rs
+// Parámetros de la simulación cargados desde un archivo TOML. Cualquier valor
+// que no aparezca en el archivo toma el valor de las constantes de modelo.rs.
+use crate::modelo::*;
+use crate::red::{direccion_entre, Red};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Escenario {
+    pub velocidad_vehiculo: f32,  // px/s
+    pub intervalo_aparicion: f32, // s
+    pub duracion_verde: f32,      // s
+    pub duracion_amarillo: f32,   // s
+    pub duracion_todo_rojo: f32,  // s con todos los accesos en rojo entre una fase y otra
+    pub fps_simulacion: u32,      // Ticks por segundo simulado
+    pub posicion_semaforo_vertical: f32,
+    pub posicion_semaforo_horizontal: f32,
+    pub red: PlanoRed,
+    pub semaforos: Vec<PosicionSemaforo>, // Del cruce en CENTRO_INTERSECCION; los demás cruces los copian
+    pub fases: Vec<Fase>,         // Se recorren en orden
+    pub proporcion_giros: ProporcionGiros,
+    pub probabilidad_emergencia: f32, // De que un vehículo generado sea ambulancia o policía
+    pub intervalo_peatones: f32,      // s entre apariciones de peatones
+    pub auxilio: bool,                // Si la ambulancia de cada choque va hasta él y lo despeja antes
+    pub control: TipoControl,
+    pub actuado: ParametrosActuado, // Solo se usan con control = "actuado"
+    pub adaptativo: ParametrosAdaptativo, // Solo se usan con control = "adaptativo"
+    pub coordinacion: Option<Coordinacion>, // Onda verde; solo con control = "fijo"
+    pub demanda: Option<Demanda>, // Sin ella, un intento de aparición cada intervalo_aparicion
+}
+
+// Grupo de direcciones que tienen verde juntas. Los tiempos que no se indiquen
+// son los generales del escenario.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fase {
+    pub direcciones: Vec<Direccion>,
+    pub verde: Option<f32>,     // s; con control fijo
+    pub amarillo: Option<f32>,  // s
+    pub todo_rojo: Option<f32>, // s después del amarillo, antes de la siguiente fase
+}
+
+impl Fase {
+    pub fn new(direcciones: &[Direccion]) -> Self {
+        Self { direcciones: direcciones.to_vec(), verde: None, amarillo: None, todo_rojo: None }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TipoControl {
+    #[default]
+    Fijo,    // Ciclo fijo de verde y amarillo
+    Actuado, // Verde según lo que leen los detectores
+    Adaptativo, // Reparte el verde de cada ciclo según colas y llegadas medidas
+}
+
+impl TipoControl {
+    pub fn nombre(self) -> &'static str {
+        match self {
+            TipoControl::Fijo => "fijo",
+            TipoControl::Actuado => "actuado",
+            TipoControl::Adaptativo => "adaptativo",
+        }
+    }
+
+    pub fn desde_nombre(nombre: &str) -> Option<Self> {
+        [TipoControl::Fijo, TipoControl::Actuado, TipoControl::Adaptativo]
+            .into_iter()
+            .find(|c| c.nombre() == nombre)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParametrosAdaptativo {
+    pub ciclo_minimo: f32,        // s
+    pub ciclo_maximo: f32,        // s
+    pub verde_minimo: f32,        // s que recibe cada fase aunque no tenga demanda
+    pub flujo_saturacion: f32,    // vehículos/s que descarga un carril en verde
+    pub distancia_contador: f32,  // px antes de la línea donde se cuentan las llegadas
+}
+
+impl Default for ParametrosAdaptativo {
+    fn default() -> Self {
+        Self {
+            ciclo_minimo: CICLO_MINIMO,
+            ciclo_maximo: CICLO_MAXIMO,
+            verde_minimo: VERDE_MINIMO,
+            flujo_saturacion: FLUJO_SATURACION,
+            distancia_contador: DISTANCIA_CONTADOR,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParametrosActuado {
+    pub verde_minimo: f32,       // s
+    pub verde_maximo: f32,       // s
+    pub brecha: f32,             // s sin detecciones para cortar el verde
+    pub largo_detector: f32,     // px antes de la línea de parada
+}
+
+impl Default for ParametrosActuado {
+    fn default() -> Self {
+        Self {
+            verde_minimo: VERDE_MINIMO,
+            verde_maximo: VERDE_MAXIMO,
+            brecha: BRECHA_DETECTOR,
+            largo_detector: LARGO_DETECTOR,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProporcionGiros {
+    pub izquierda: f32,
+    pub derecha: f32,
+}
+
+impl Default for ProporcionGiros {
+    fn default() -> Self {
+        Self { izquierda: PROPORCION_GIRO_IZQUIERDA, derecha: PROPORCION_GIRO_DERECHA }
+    }
+}
+
+// Coordinación de los cruces con control fijo: todos usan el mismo ciclo y cada
+// uno lo empieza con un desfase respecto al reloj de la simulación
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Coordinacion {
+    pub ciclo: f32,             // s; el verde que sobra se reparte entre las fases
+    pub velocidad: Option<f32>, // px/s de la onda verde; por defecto velocidad_vehiculo
+    #[serde(default)]
+    pub corredor: Vec<usize>,   // Cruces en el orden en que los recorre la onda verde
+}
+
+// Llegadas de vehículos por los extremos de la red. En cada extremo llegan al
+// azar (proceso de Poisson) con un flujo medio en vehículos por hora, que el
+// perfil multiplica según la hora: una lista de puntos [s, factor] que se
+// interpolan, y después del último se mantiene su factor.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Demanda {
+    pub flujo: f32,                   // veh/h de cada extremo que no esté en `accesos`
+    pub perfil: Vec<[f32; 2]>,        // Vacío: el flujo es siempre el mismo
+    pub accesos: Vec<DemandaAcceso>,
+}
+
+impl Default for Demanda {
+    fn default() -> Self {
+        Self { flujo: FLUJO_POR_ACCESO, perfil: Vec::new(), accesos: Vec::new() }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DemandaAcceso {
+    pub nodo: usize,                  // Extremo de la red por donde entran
+    pub flujo: f32,                   // veh/h
+    pub perfil: Option<Vec<[f32; 2]>>, // Por defecto el de [demanda]
+}
+
+// Plano de la red de calles: nodos (cruces y extremos) y vías de doble sentido
+// entre ellos. Los vehículos aparecen y salen por los extremos, los nodos con
+// una sola vía.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlanoRed {
+    pub nodos: Vec<NodoPlano>,
+    pub vias: Vec<ViaPlano>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodoPlano {
+    pub posicion: [f32; 2],
+    pub semaforo: Option<bool>, // Por defecto, los cruces de tres o más vías
+    pub desfase: Option<f32>,   // s; reemplaza el que se calcula para el corredor
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ViaPlano {
+    pub nodos: [usize; 2], // Índices en `nodos`; la vía tiene que ser horizontal o vertical
+    #[serde(default = "carriles_por_defecto")]
+    pub carriles: usize,   // Por sentido
+}
+
+fn carriles_por_defecto() -> usize {
+    CARRILES_POR_SENTIDO
+}
+
+impl Default for PlanoRed {
+    fn default() -> Self {
+        Self {
+            nodos: NODOS_RED.iter().map(|&posicion| NodoPlano { posicion, semaforo: None, desfase: None }).collect(),
+            vias: VIAS_RED.iter().map(|&nodos| ViaPlano { nodos, carriles: CARRILES_POR_SENTIDO }).collect(),
+        }
+    }
+}
+
+impl PlanoRed {
+    // Tamaño del mapa: los extremos quedan en los bordes de la ventana
+    pub fn dimensiones(&self) -> [f32; 2] {
+        self.nodos.iter().fold([0.0, 0.0], |[ancho, alto], nodo| {
+            [ancho.max(nodo.posicion[0]), alto.max(nodo.posicion[1])]
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PosicionSemaforo {
+    pub direccion: Direccion,
+    pub posicion: [f32; 2],
+}
+
+impl Default for Escenario {
+    fn default() -> Self {
+        Self {
+            velocidad_vehiculo: VELOCIDAD_VEHICULO as f32,
+            intervalo_aparicion: INTERVALO_APARICION as f32,
+            duracion_verde: DURACION_VERDE as f32,
+            duracion_amarillo: DURACION_AMARILLO as f32,
+            duracion_todo_rojo: DURACION_TODO_ROJO,
+            fps_simulacion: FPS_SIMULACION as u32,
+            posicion_semaforo_vertical: POSICION_SEMAFORO_VERTICAL,
+            posicion_semaforo_horizontal: POSICION_SEMAFORO_HORIZONTAL,
+            red: PlanoRed::default(),
+            semaforos: SEMAFOROS.iter()
+                .map(|(posicion, direccion)| PosicionSemaforo { direccion: *direccion, posicion: *posicion })
+                .collect(),
+            fases: FASES.iter()
+                .map(|direcciones| Fase::new(direcciones))
+                .collect(),
+            proporcion_giros: ProporcionGiros::default(),
+            probabilidad_emergencia: PROBABILIDAD_EMERGENCIA,
+            intervalo_peatones: INTERVALO_PEATONES,
+            auxilio: AUXILIO_EN_CHOQUES,
+            control: TipoControl::default(),
+            actuado: ParametrosActuado::default(),
+            adaptativo: ParametrosAdaptativo::default(),
+            coordinacion: None,
+            demanda: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ErrorEscenario {
+    Lectura(std::io::Error),
+    Formato(toml::de::Error),
+    ValorInvalido { campo: String, motivo: String },
+}
+
+impl fmt::Display for ErrorEscenario {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorEscenario::Lectura(e) => write!(f, "No se pudo leer el escenario: {}", e),
+            ErrorEscenario::Formato(e) => write!(f, "El escenario no es un TOML válido: {}", e),
+            ErrorEscenario::ValorInvalido { campo, motivo } => write!(f, "Valor inválido en `{}`: {}", campo, motivo),
+        }
+    }
+}
+
+impl std::error::Error for ErrorEscenario {}
+
+fn invalido(campo: impl Into<String>, motivo: impl Into<String>) -> ErrorEscenario {
+    ErrorEscenario::ValorInvalido { campo: campo.into(), motivo: motivo.into() }
+}
+
+impl Escenario {
+    pub fn cargar(ruta: &Path) -> Result<Self, ErrorEscenario> {
+        let texto = std::fs::read_to_string(ruta).map_err(ErrorEscenario::Lectura)?;
+        Self::desde_toml(&texto)
+    }
+
+    pub fn desde_toml(texto: &str) -> Result<Self, ErrorEscenario> {
+        let escenario: Escenario = toml::from_str(texto).map_err(ErrorEscenario::Formato)?;
+        escenario.validar()?;
+        Ok(escenario)
+    }
+
+    pub fn validar(&self) -> Result<(), ErrorEscenario> {
+        let positivos = [
+            ("velocidad_vehiculo", self.velocidad_vehiculo),
+            ("intervalo_aparicion", self.intervalo_aparicion),
+            ("intervalo_peatones", self.intervalo_peatones),
+            ("duracion_verde", self.duracion_verde),
+            ("duracion_amarillo", self.duracion_amarillo),
+            ("actuado.verde_minimo", self.actuado.verde_minimo),
+            ("actuado.verde_maximo", self.actuado.verde_maximo),
+            ("actuado.brecha", self.actuado.brecha),
+            ("actuado.largo_detector", self.actuado.largo_detector),
+            ("adaptativo.ciclo_minimo", self.adaptativo.ciclo_minimo),
+            ("adaptativo.ciclo_maximo", self.adaptativo.ciclo_maximo),
+            ("adaptativo.verde_minimo", self.adaptativo.verde_minimo),
+            ("adaptativo.flujo_saturacion", self.adaptativo.flujo_saturacion),
+        ];
+        for (campo, valor) in positivos {
+            if !(valor.is_finite() && valor > 0.0) {
+                return Err(invalido(campo, format!("debe ser mayor que cero (se recibió {})", valor)));
+            }
+        }
+
+        if self.actuado.verde_maximo < self.actuado.verde_minimo {
+            return Err(invalido("actuado.verde_maximo", "no puede ser menor que verde_minimo"));
+        }
+
+        if self.adaptativo.ciclo_maximo < self.adaptativo.ciclo_minimo {
+            return Err(invalido("adaptativo.ciclo_maximo", "no puede ser menor que ciclo_minimo"));
+        }
+
+        let distancia = self.adaptativo.distancia_contador;
+        if !(distancia.is_finite() && distancia >= 0.0) {
+            return Err(invalido("adaptativo.distancia_contador", "no puede ser negativa"));
+        }
+
+        if !(1..=1000).contains(&self.fps_simulacion) {
+            return Err(invalido("fps_simulacion", format!("debe estar entre 1 y 1000 (se recibió {})", self.fps_simulacion)));
+        }
+
+        for (campo, direccion) in [("posicion_semaforo_vertical", Direccion::Este), ("posicion_semaforo_horizontal", Direccion::Norte)] {
+            let retiro = self.retiro_linea_parada(direccion);
+            if !(retiro.is_finite() && retiro >= MEDIA_CAJA) {
+                return Err(invalido(campo, "la línea de parada quedaría dentro de la intersección"));
+            }
+        }
+
+        for (i, semaforo) in self.semaforos.iter().enumerate() {
+            let campo = format!("semaforos[{}]", i);
+            if self.semaforos[..i].iter().any(|s| s.direccion == semaforo.direccion) {
+                return Err(invalido(campo, format!("ya hay un semáforo para la dirección \"{}\"", semaforo.direccion)));
+            }
+        }
+
+        self.validar_red()?;
+
+        let giros = &self.proporcion_giros;
+        for (campo, valor) in [("proporcion_giros.izquierda", giros.izquierda), ("proporcion_giros.derecha", giros.derecha)] {
+            if !(0.0..=1.0).contains(&valor) {
+                return Err(invalido(campo, format!("debe estar entre 0 y 1 (se recibió {})", valor)));
+            }
+        }
+        if giros.izquierda + giros.derecha > 1.0 {
+            return Err(invalido("proporcion_giros", "izquierda + derecha no puede pasar de 1"));
+        }
+
+        if !(0.0..=1.0).contains(&self.probabilidad_emergencia) {
+            return Err(invalido("probabilidad_emergencia", format!("debe estar entre 0 y 1 (se recibió {})", self.probabilidad_emergencia)));
+        }
+
+        if !(self.duracion_todo_rojo.is_finite() && self.duracion_todo_rojo >= 0.0) {
+            return Err(invalido("duracion_todo_rojo", format!("no puede ser negativa (se recibió {})", self.duracion_todo_rojo)));
+        }
+
+        if self.fases.is_empty() {
+            return Err(invalido("fases", "se necesita al menos una fase"));
+        }
+
+        for (i, fase) in self.fases.iter().enumerate() {
+            let campo = format!("fases[{}]", i);
+            if fase.direcciones.is_empty() {
+                return Err(invalido(campo, "la fase no tiene direcciones"));
+            }
+            if let Some(direccion) = fase.direcciones.iter().find(|&&d| !self.semaforos.iter().any(|s| s.direccion == d)) {
+                return Err(invalido(campo, format!("no hay semáforo para la dirección \"{}\"", direccion)));
+            }
+            for (nombre, valor) in [("verde", fase.verde), ("amarillo", fase.amarillo)] {
+                if let Some(valor) = valor.filter(|v| !(v.is_finite() && *v > 0.0)) {
+                    return Err(invalido(format!("{}.{}", campo, nombre), format!("debe ser mayor que cero (se recibió {})", valor)));
+                }
+            }
+            if let Some(valor) = fase.todo_rojo.filter(|v| !(v.is_finite() && *v >= 0.0)) {
+                return Err(invalido(format!("{}.todo_rojo", campo), format!("no puede ser negativo (se recibió {})", valor)));
+            }
+        }
+
+        for semaforo in &self.semaforos {
+            if !self.fases.iter().any(|fase| fase.direcciones.contains(&semaforo.direccion)) {
+                return Err(invalido("fases", format!("la dirección \"{}\" nunca recibe verde", semaforo.direccion)));
+            }
+        }
+
+        self.validar_coordinacion()?;
+        self.validar_demanda()
+    }
+
+    fn validar_demanda(&self) -> Result<(), ErrorEscenario> {
+        let Some(demanda) = &self.demanda else {
+            return Ok(());
+        };
+
+        let red = Red::new(&self.red);
+        // El flujo y el perfil generales, y los de cada acceso
+        let flujos = std::iter::once(("demanda".to_string(), demanda.flujo, Some(&demanda.perfil)))
+            .chain(demanda.accesos.iter().enumerate()
+                .map(|(i, acceso)| (format!("demanda.accesos[{}]", i), acceso.flujo, acceso.perfil.as_ref())));
+        for (campo, flujo, perfil) in flujos {
+            if !(flujo.is_finite() && flujo >= 0.0) {
+                return Err(invalido(format!("{}.flujo", campo), format!("no puede ser negativo (se recibió {})", flujo)));
+            }
+            let perfil = perfil.map_or(&[][..], Vec::as_slice);
+            if perfil.iter().any(|&[t, factor]| !(t.is_finite() && t >= 0.0 && factor.is_finite() && factor >= 0.0)) {
+                return Err(invalido(format!("{}.perfil", campo), "los tiempos y los factores no pueden ser negativos"));
+            }
+            if perfil.windows(2).any(|par| par[1][0] <= par[0][0]) {
+                return Err(invalido(format!("{}.perfil", campo), "los tiempos tienen que ir en aumento"));
+            }
+        }
+
+        for (i, acceso) in demanda.accesos.iter().enumerate() {
+            let campo = format!("demanda.accesos[{}].nodo", i);
+            if acceso.nodo >= red.nodos.len() || !red.es_extremo(acceso.nodo) {
+                return Err(invalido(campo, format!("el nodo {} no es un extremo de la red", acceso.nodo)));
+            }
+            if demanda.accesos[..i].iter().any(|otro| otro.nodo == acceso.nodo) {
+                return Err(invalido(campo, format!("el nodo {} ya tiene su demanda", acceso.nodo)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validar_coordinacion(&self) -> Result<(), ErrorEscenario> {
+        let Some(coordinacion) = &self.coordinacion else {
+            return match self.red.nodos.iter().position(|n| n.desfase.is_some()) {
+                Some(i) => Err(invalido(format!("red.nodos[{}].desfase", i), "solo se usa con [coordinacion]")),
+                None => Ok(()),
+            };
+        };
+
+        let perdido: f32 = (0..self.fases.len()).map(|i| self.entreverde(i)).sum();
+        if !(coordinacion.ciclo.is_finite() && coordinacion.ciclo > perdido) {
+            return Err(invalido("coordinacion.ciclo", format!("tiene que ser mayor que la suma de los entreverdes ({} s)", perdido)));
+        }
+        if let Some(velocidad) = coordinacion.velocidad.filter(|v| !(v.is_finite() && *v > 0.0)) {
+            return Err(invalido("coordinacion.velocidad", format!("debe ser mayor que cero (se recibió {})", velocidad)));
+        }
+        for (i, nodo) in self.red.nodos.iter().enumerate() {
+            if let Some(desfase) = nodo.desfase.filter(|d| !d.is_finite()) {
+                return Err(invalido(format!("red.nodos[{}].desfase", i), format!("valor inválido {}", desfase)));
+            }
+        }
+
+        let corredor = &coordinacion.corredor;
+        if corredor.len() == 1 {
+            return Err(invalido("coordinacion.corredor", "el corredor necesita al menos dos cruces"));
+        }
+        let red = Red::new(&self.red);
+        if let Some(&nodo) = corredor.iter().find(|&&n| n >= red.nodos.len() || !red.nodos[n].semaforo) {
+            return Err(invalido("coordinacion.corredor", format!("el nodo {} no es un cruce con semáforos", nodo)));
+        }
+        // Todos en línea recta y en el mismo sentido
+        let sentidos: Vec<_> = corredor.windows(2)
+            .map(|par| direccion_entre(red.nodos[par[0]].posicion, red.nodos[par[1]].posicion))
+            .collect();
+        if sentidos.iter().any(|s| s.is_none() || *s != sentidos[0]) {
+            return Err(invalido("coordinacion.corredor", "los cruces tienen que estar en línea recta y en orden"));
+        }
+
+        Ok(())
+    }
+
+    fn validar_red(&self) -> Result<(), ErrorEscenario> {
+        let nodos = &self.red.nodos;
+        for (i, nodo) in nodos.iter().enumerate() {
+            if !nodo.posicion.iter().all(|c| c.is_finite() && *c >= 0.0) {
+                return Err(invalido(format!("red.nodos[{}]", i), format!("posición inválida {:?}", nodo.posicion)));
+            }
+        }
+
+        for (i, via) in self.red.vias.iter().enumerate() {
+            let campo = format!("red.vias[{}]", i);
+            let [a, b] = via.nodos;
+            if a >= nodos.len() || b >= nodos.len() {
+                return Err(invalido(campo, format!("hay {} nodos y se usó el nodo {}", nodos.len(), a.max(b))));
+            }
+            let Some(direccion) = direccion_entre(nodos[a].posicion, nodos[b].posicion) else {
+                return Err(invalido(campo, "la vía tiene que ser horizontal o vertical y unir dos puntos distintos"));
+            };
+            if !(1..=MAXIMO_CARRILES).contains(&via.carriles) {
+                return Err(invalido(format!("{}.carriles", campo), format!("tiene que haber de 1 a {} carriles por sentido", MAXIMO_CARRILES)));
+            }
+            // Ningún nodo puede tener dos vías hacia el mismo lado
+            let repetida = self.red.vias[..i].iter().any(|otra| {
+                let [c, d] = otra.nodos;
+                [(c, d), (d, c)].into_iter().any(|(desde, hasta)| {
+                    let otra_direccion = direccion_entre(nodos[desde].posicion, nodos[hasta].posicion);
+                    (desde == a && otra_direccion == Some(direccion)) || (desde == b && otra_direccion == Some(direccion.opuesta()))
+                })
+            });
+            if repetida {
+                return Err(invalido(campo, "uno de sus nodos ya tiene otra vía hacia el mismo lado"));
+            }
+        }
+
+        let red = Red::new(&self.red);
+        for (i, nodo) in red.nodos.iter().enumerate() {
+            if nodo.salientes.is_empty() {
+                return Err(invalido(format!("red.nodos[{}]", i), "el nodo no está unido a ninguna vía"));
+            }
+            if !nodo.semaforo {
+                continue;
+            }
+            for &enlace in &nodo.entrantes {
+                let direccion = red.enlaces[enlace].direccion;
+                if self.posicion_semaforo(direccion, nodo.posicion).is_none() {
+                    return Err(invalido(format!("red.nodos[{}]", i), format!("llega una vía en dirección \"{}\" y no hay semáforo para esa dirección", direccion)));
+                }
+            }
+        }
+
+        if red.entradas.is_empty() {
+            return Err(invalido("red", "no hay extremos (nodos con una sola vía) por donde entren vehículos"));
+        }
+
+        for (i, enlace) in red.enlaces.iter().enumerate() {
+            // La línea de parada tiene que quedar después del cruce anterior; en los
+            // cruces de vías anchas la caja es más grande y la línea se corre atrás
+            let ensanche = red.nodos[enlace.hasta].media_caja - MEDIA_CAJA;
+            if red.nodos[enlace.hasta].semaforo && red.nodos[enlace.desde].semaforo
+                && enlace.largo < self.retiro_linea_parada(enlace.direccion) + ensanche + red.nodos[enlace.desde].media_caja {
+                return Err(invalido("red.vias", format!("la vía entre los nodos {} y {} es demasiado corta", enlace.desde, enlace.hasta)));
+            }
+            if red.entradas.contains(&i) && red.extremo_cercano(i).is_none() {
+                return Err(invalido("red", format!("los vehículos que entran por el nodo {} no tienen por dónde salir", enlace.desde)));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Posición del semáforo de un acceso en el cruce con centro en `cruce`
+    pub fn posicion_semaforo(&self, direccion: Direccion, cruce: [f32; 2]) -> Option<[f32; 2]> {
+        self.semaforos.iter()
+            .find(|s| s.direccion == direccion)
+            .map(|s| [
+                s.posicion[0] - CENTRO_INTERSECCION[0] + cruce[0],
+                s.posicion[1] - CENTRO_INTERSECCION[1] + cruce[1],
+            ])
+    }
+
+    // Distancia del centro de un cruce a la línea de parada de un acceso. Las
+    // posiciones del escenario son las del cruce en CENTRO_INTERSECCION, y oeste
+    // y sur usan el reflejo de las de este y norte.
+    pub fn retiro_linea_parada(&self, direccion: Direccion) -> f32 {
+        if direccion.eje_horizontal() {
+            CENTRO_INTERSECCION[0] - self.posicion_semaforo_vertical
+        } else {
+            self.posicion_semaforo_horizontal - CENTRO_INTERSECCION[1]
+        }
+    }
+
+    // Tiempos de cada fase, con los generales del escenario como respaldo
+    pub fn verde(&self, fase: usize) -> f32 {
+        self.fases[fase].verde.unwrap_or(self.duracion_verde)
+    }
+
+    pub fn amarillo(&self, fase: usize) -> f32 {
+        self.fases[fase].amarillo.unwrap_or(self.duracion_amarillo)
+    }
+
+    pub fn todo_rojo(&self, fase: usize) -> f32 {
+        self.fases[fase].todo_rojo.unwrap_or(self.duracion_todo_rojo)
+    }
+
+    // Amarillo más todo rojo al terminar la fase
+    pub fn entreverde(&self, fase: usize) -> f32 {
+        self.amarillo(fase) + self.todo_rojo(fase)
+    }
+
+    pub fn paso(&self) -> f32 {
+        1.0 / self.fps_simulacion as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(texto: &str) -> String {
+        Escenario::desde_toml(texto).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn las_duraciones_tienen_que_ser_positivas() {
+        assert_eq!(error("duracion_verde = 0.0"), "Valor inválido en `duracion_verde`: debe ser mayor que cero (se recibió 0)");
+        assert_eq!(error("duracion_amarillo = -3.0"), "Valor inválido en `duracion_amarillo`: debe ser mayor que cero (se recibió -3)");
+        assert_eq!(error("duracion_todo_rojo = -1.0"), "Valor inválido en `duracion_todo_rojo`: no puede ser negativa (se recibió -1)");
+        assert_eq!(
+            error("[[fases]]\ndirecciones = [\"este\", \"oeste\", \"norte\", \"sur\"]\nverde = 0.0"),
+            "Valor inválido en `fases[0].verde`: debe ser mayor que cero (se recibió 0)"
+        );
+    }
+
+    #[test]
+    fn los_fps_no_pueden_ser_cero() {
+        assert_eq!(error("fps_simulacion = 0"), "Valor inválido en `fps_simulacion`: debe estar entre 1 y 1000 (se recibió 0)");
+    }
+
+    #[test]
+    fn las_vias_tienen_que_usar_nodos_del_plano() {
+        let texto = "[red]\nnodos = [{ posicion = [0.0, 100.0] }, { posicion = [200.0, 100.0] }]\nvias = [{ nodos = [0, 5] }]";
+        assert_eq!(error(texto), "Valor inválido en `red.vias[0]`: hay 2 nodos y se usó el nodo 5");
+    }
+
+    #[test]
+    fn todos_los_nodos_tienen_que_estar_unidos() {
+        let texto = r#"
+            [red]
+            nodos = [{ posicion = [0.0, 100.0] }, { posicion = [200.0, 100.0] }, { posicion = [100.0, 300.0] }]
+            vias = [{ nodos = [0, 1] }]
+        "#;
+        assert_eq!(error(texto), "Valor inválido en `red.nodos[2]`: el nodo no está unido a ninguna vía");
+    }
+
+    #[test]
+    fn la_red_necesita_extremos_por_donde_entrar() {
+        // Un anillo: todos los nodos tienen dos vías
+        let texto = r#"
+            [red]
+            nodos = [
+                { posicion = [100.0, 100.0] }, { posicion = [300.0, 100.0] },
+                { posicion = [300.0, 300.0] }, { posicion = [100.0, 300.0] },
+            ]
+            vias = [{ nodos = [0, 1] }, { nodos = [1, 2] }, { nodos = [2, 3] }, { nodos = [3, 0] }]
+        "#;
+        assert_eq!(error(texto), "Valor inválido en `red`: no hay extremos (nodos con una sola vía) por donde entren vehículos");
+    }
+
+    #[test]
+    fn la_demanda_entra_solo_por_extremos() {
+        let acceso = |nodo: usize| format!("[demanda]\naccesos = [{{ nodo = {}, flujo = 300.0 }}]", nodo);
+        // El nodo 0 es el cruce de la red por defecto
+        assert_eq!(error(&acceso(0)), "Valor inválido en `demanda.accesos[0].nodo`: el nodo 0 no es un extremo de la red");
+        assert_eq!(error(&acceso(99)), "Valor inválido en `demanda.accesos[0].nodo`: el nodo 99 no es un extremo de la red");
+        assert!(Escenario::desde_toml(&acceso(1)).is_ok());
+    }
+
+    #[test]
+    fn los_campos_desconocidos_se_rechazan() {
+        for (texto, campo) in [("duracion_verd = 5.0", "duracion_verd"), ("[actuado]\nbrecha_maxima = 2.0", "brecha_maxima")] {
+            let mensaje = error(texto);
+            assert!(mensaje.starts_with("El escenario no es un TOML válido: "), "{}", mensaje);
+            assert!(mensaje.contains(&format!("unknown field `{}`", campo)), "{}", mensaje);
+        }
+    }
+
+    #[test]
+    fn el_escenario_por_defecto_vuelve_igual_del_toml() {
+        let texto = toml::to_string(&Escenario::default()).unwrap();
+        let leido = Escenario::desde_toml(&texto).unwrap();
+        assert_eq!(toml::to_string(&leido).unwrap(), texto);
+    }
+
+    #[test]
+    fn cargan_los_escenarios_de_ejemplo() {
+        let ejemplos = [
+            ("escenario_ejemplo.toml", include_str!("../escenario_ejemplo.toml")),
+            ("escenario_corredor.toml", include_str!("../escenario_corredor.toml")),
+            ("escenario_cuadricula.toml", include_str!("../escenario_cuadricula.toml")),
+            ("escenario_hora_punta.toml", include_str!("../escenario_hora_punta.toml")),
+        ];
+        for (nombre, texto) in ejemplos {
+            if let Err(e) = Escenario::desde_toml(texto) {
+                panic!("{}: {}", nombre, e);
+            }
+        }
+    }
+}
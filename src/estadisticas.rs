@@ -0,0 +1,258 @@
+// estadisticas.rs
+// Métricas de la corrida: un registro por cada viaje terminado y contadores por
+// acceso semaforizado. El motor de física las actualiza en cada tick, así se
+// pueden mostrar en vivo y en el resumen final.
+use crate::modelo::{Carro, Direccion, Semaforo, TipoVehiculo};
+use serde::{Deserialize, Serialize};
+
+// Viaje de un vehículo que salió por un extremo de la red
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Viaje {
+    pub id: usize,
+    pub tipo: TipoVehiculo,
+    pub origen: usize,  // Enlace por el que entró
+    pub destino: usize, // Nodo extremo por el que salió
+    pub aparicion: f32, // s simulados
+    pub salida: f32,
+    pub demora: f32,    // s perdidos respecto a ir a la velocidad deseada
+    pub paradas: u32,
+}
+
+impl Viaje {
+    pub fn duracion(&self) -> f32 {
+        self.salida - self.aparicion
+    }
+}
+
+// Contadores de un acceso con semáforo
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Acceso {
+    pub nodo: usize,
+    pub direccion: Direccion,
+    pub enlace: usize,
+    pub vehiculos: usize,   // Los que pasaron la línea de parada
+    pub demora: f64,        // s perdidos antes de la línea, sumando todos los vehículos
+    pub cola_maxima: usize, // Vehículos detenidos antes de la línea, el máximo visto
+}
+
+impl Acceso {
+    // Vehículos por hora que pasaron la línea
+    pub fn flujo(&self, tiempo: f32) -> f32 {
+        if tiempo > 0.0 { self.vehiculos as f32 * 3600.0 / tiempo } else { 0.0 }
+    }
+
+    // Demora de control: la perdida en el acceso por cada vehículo que lo cruzó
+    pub fn demora_control(&self) -> f64 {
+        self.demora / self.vehiculos.max(1) as f64
+    }
+}
+
+// Promedios de los viajes terminados
+#[derive(Clone, Copy, Debug)]
+pub struct ResumenViajes {
+    pub cantidad: usize,
+    pub duracion_media: f32,
+    pub duracion_p95: f32,
+    pub demora_media: f32,
+    pub paradas_medias: f32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Estadisticas {
+    pub viajes: Vec<Viaje>,
+    pub accesos: Vec<Acceso>, // Mismo orden que los semáforos
+    acceso_por_enlace: Vec<Option<usize>>,
+}
+
+impl Estadisticas {
+    pub fn new(semaforos: &[Semaforo], num_enlaces: usize) -> Self {
+        let mut acceso_por_enlace = vec![None; num_enlaces];
+        for (i, semaforo) in semaforos.iter().enumerate() {
+            acceso_por_enlace[semaforo.enlace] = Some(i);
+        }
+        let accesos = semaforos.iter()
+            .map(|s| Acceso { nodo: s.nodo, direccion: s.direccion, enlace: s.enlace, vehiculos: 0, demora: 0.0, cola_maxima: 0 })
+            .collect();
+        Self { viajes: Vec::new(), accesos, acceso_por_enlace }
+    }
+
+    // Acceso semaforizado al que llega el enlace, si lo hay
+    pub fn acceso_de(&self, enlace: usize) -> Option<usize> {
+        self.acceso_por_enlace[enlace]
+    }
+
+    // Guarda el viaje de un vehículo que ya tiene hora de salida
+    pub fn registrar_salida(&mut self, carro: &Carro) {
+        let Some(salida) = carro.salida else { return };
+        self.viajes.push(Viaje {
+            id: carro.id,
+            tipo: carro.tipo,
+            origen: carro.origen,
+            destino: carro.destino,
+            aparicion: carro.aparicion,
+            salida,
+            demora: carro.demora,
+            paradas: carro.paradas,
+        });
+    }
+
+    // Colas de este tick, una por acceso
+    pub fn registrar_colas(&mut self, colas: &[usize]) {
+        for (acceso, &cola) in self.accesos.iter_mut().zip(colas) {
+            acceso.cola_maxima = acceso.cola_maxima.max(cola);
+        }
+    }
+
+    pub fn resumen_viajes(&self) -> Option<ResumenViajes> {
+        if self.viajes.is_empty() {
+            return None;
+        }
+        let n = self.viajes.len();
+        let mut duraciones: Vec<f32> = self.viajes.iter().map(Viaje::duracion).collect();
+        duraciones.sort_by(f32::total_cmp);
+        // Percentil por rango más cercano
+        let p95 = duraciones[(n as f32 * 0.95).ceil() as usize - 1];
+        Some(ResumenViajes {
+            cantidad: n,
+            duracion_media: duraciones.iter().sum::<f32>() / n as f32,
+            duracion_p95: p95,
+            demora_media: self.viajes.iter().map(|v| v.demora).sum::<f32>() / n as f32,
+            paradas_medias: self.viajes.iter().map(|v| v.paradas as f32).sum::<f32>() / n as f32,
+        })
+    }
+
+    // Tabla de texto con los viajes y los accesos, para la terminal y la ventana
+    pub fn informe(&self, tiempo: f32) -> String {
+        let mut texto = match self.resumen_viajes() {
+            Some(r) => format!(
+                "Viajes terminados: {}\nTiempo de viaje: {:.1} s de media, {:.1} s el p95\nDemora por viaje: {:.1} s, paradas por viaje: {:.2}\n",
+                r.cantidad, r.duracion_media, r.duracion_p95, r.demora_media, r.paradas_medias
+            ),
+            None => "Viajes terminados: 0\n".to_string(),
+        };
+        if !self.accesos.is_empty() {
+            texto.push_str("Acceso            veh/h    demora cola máx.\n");
+            for acceso in &self.accesos {
+                texto.push_str(&format!(
+                    "nodo {:<3} {:<6} {:>7.0} {:>7.1} s {:>9}\n",
+                    acceso.nodo, acceso.direccion.nombre(), acceso.flujo(tiempo), acceso.demora_control(), acceso.cola_maxima
+                ));
+            }
+        }
+        texto
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modelo::EstadoSemaforo;
+
+    fn semaforo(nodo: usize, direccion: Direccion, enlace: usize) -> Semaforo {
+        Semaforo { posicion: [0.0, 0.0], estado: EstadoSemaforo::Rojo, direccion, nodo, enlace }
+    }
+
+    // Dos accesos del cruce 0: el este llega por el enlace 1 y el norte por el 3
+    fn estadisticas() -> Estadisticas {
+        Estadisticas::new(&[semaforo(0, Direccion::Este, 1), semaforo(0, Direccion::Norte, 3)], 4)
+    }
+
+    fn viaje(aparicion: f32, salida: f32, demora: f32, paradas: u32) -> Viaje {
+        Viaje { id: 0, tipo: TipoVehiculo::Automovil, origen: 1, destino: 2, aparicion, salida, demora, paradas }
+    }
+
+    #[test]
+    fn sin_viajes_no_hay_resumen() {
+        let estadisticas = estadisticas();
+        assert!(estadisticas.resumen_viajes().is_none());
+        assert!(estadisticas.informe(0.0).starts_with("Viajes terminados: 0\n"));
+    }
+
+    #[test]
+    fn con_un_viaje_el_p95_es_ese_viaje() {
+        let mut estadisticas = estadisticas();
+        estadisticas.viajes.push(viaje(10.0, 22.5, 3.0, 1));
+        let resumen = estadisticas.resumen_viajes().unwrap();
+        assert_eq!(resumen.cantidad, 1);
+        assert_eq!(resumen.duracion_media, 12.5);
+        assert_eq!(resumen.duracion_p95, 12.5);
+    }
+
+    #[test]
+    fn el_p95_es_el_del_rango_mas_cercano() {
+        // Duraciones de 1 a 20 s en desorden: el rango es ceil(0.95 * 20) = 19
+        let mut estadisticas = estadisticas();
+        for d in (1..=20).rev() {
+            estadisticas.viajes.push(viaje(100.0, 100.0 + d as f32, 0.0, 0));
+        }
+        assert_eq!(estadisticas.resumen_viajes().unwrap().duracion_p95, 19.0);
+
+        // Con 21 el rango es ceil(19.95) = 20
+        estadisticas.viajes.push(viaje(100.0, 121.0, 0.0, 0));
+        let resumen = estadisticas.resumen_viajes().unwrap();
+        assert_eq!(resumen.duracion_p95, 20.0);
+        assert_eq!(resumen.duracion_media, 11.0);
+    }
+
+    #[test]
+    fn demora_y_paradas_por_viaje() {
+        let mut estadisticas = estadisticas();
+        estadisticas.viajes.push(viaje(0.0, 10.0, 2.0, 0));
+        estadisticas.viajes.push(viaje(0.0, 20.0, 4.0, 1));
+        estadisticas.viajes.push(viaje(0.0, 30.0, 6.0, 5));
+        let resumen = estadisticas.resumen_viajes().unwrap();
+        assert_eq!(resumen.demora_media, 4.0);
+        assert_eq!(resumen.paradas_medias, 2.0);
+        assert_eq!(resumen.duracion_media, 20.0);
+    }
+
+    #[test]
+    fn flujo_y_demora_de_control_por_acceso() {
+        let mut estadisticas = estadisticas();
+        assert_eq!(estadisticas.acceso_de(1), Some(0));
+        assert_eq!(estadisticas.acceso_de(3), Some(1));
+        assert_eq!(estadisticas.acceso_de(0), None);
+
+        let este = &mut estadisticas.accesos[0];
+        este.vehiculos = 30;
+        este.demora = 90.0;
+        // 30 vehículos en 10 minutos
+        assert_eq!(este.flujo(600.0), 180.0);
+        assert_eq!(este.flujo(0.0), 0.0);
+        assert_eq!(este.demora_control(), 3.0);
+
+        // Sin vehículos que pasaran la línea la demora no se divide por cero
+        let norte = &mut estadisticas.accesos[1];
+        norte.demora = 7.0;
+        assert_eq!(norte.demora_control(), 7.0);
+    }
+
+    #[test]
+    fn la_cola_maxima_es_la_mayor_vista() {
+        let mut estadisticas = estadisticas();
+        for colas in [[3, 0], [1, 4], [2, 2]] {
+            estadisticas.registrar_colas(&colas);
+        }
+        assert_eq!(estadisticas.accesos[0].cola_maxima, 3);
+        assert_eq!(estadisticas.accesos[1].cola_maxima, 4);
+    }
+
+    #[test]
+    fn el_informe_lleva_viajes_y_accesos() {
+        let mut estadisticas = estadisticas();
+        estadisticas.viajes.push(viaje(0.0, 10.0, 2.0, 1));
+        estadisticas.viajes.push(viaje(0.0, 30.0, 4.0, 2));
+        estadisticas.accesos[0].vehiculos = 30;
+        estadisticas.accesos[0].demora = 90.0;
+        estadisticas.registrar_colas(&[5, 2]);
+        assert_eq!(
+            estadisticas.informe(600.0),
+            "Viajes terminados: 2\n\
+             Tiempo de viaje: 20.0 s de media, 30.0 s el p95\n\
+             Demora por viaje: 3.0 s, paradas por viaje: 1.50\n\
+             Acceso            veh/h    demora cola máx.\n\
+             nodo 0   este       180     3.0 s         5\n\
+             nodo 0   norte        0     0.0 s         2\n"
+        );
+    }
+}
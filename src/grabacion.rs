@@ -0,0 +1,248 @@
+// grabacion.rs
+// Grabación de una corrida para volver a verla: cada tanto la física guarda un
+// cuadro con todo lo que se dibuja (vehículos, semáforos, peatones, fases y
+// choques) en un archivo binario. Al reproducirlo la vista lee los cuadros del
+// archivo en vez de los hilos de la simulación.
+use crate::escenario::Escenario;
+use crate::modelo::*;
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+pub const CUADROS_POR_SEGUNDO: u64 = 20; // Cuadros por segundo simulado; uno por tick si el escenario tiene menos ticks
+const MARCA: [u8; 4] = *b"SIMG";
+const VERSION: u32 = 1;
+
+// Lo que se ve de la simulación en un momento
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Cuadro {
+    pub tiempo: f32, // s simulados
+    pub carros: Vec<Carro>,
+    pub semaforos: Vec<Semaforo>,
+    pub direccion_activa: Vec<Vec<Direccion>>,
+    pub semaforos_peatonales: Vec<EstadoPeatonal>,
+    pub peatones: Vec<Peaton>,
+    pub accidentes: usize,
+    pub atropellos: usize,
+    pub choques: Vec<Choque>, // Solo los que todavía se marcan en la vista
+}
+
+impl Cuadro {
+    // Copia el estado compartido; cada lock se suelta antes de tomar el siguiente
+    pub fn de(compartido: &EstadoCompartido, tiempo: f32) -> Self {
+        let choques = compartido.choques.lock().unwrap();
+        let recientes = choques.iter().rev()
+            .take_while(|c| tiempo - c.tiempo < DURACION_MARCA_CHOQUE)
+            .count();
+        let choques = choques[choques.len() - recientes..].to_vec();
+        Self {
+            tiempo,
+            carros: compartido.carros.lock().unwrap().clone(),
+            semaforos: compartido.semaforos.lock().unwrap().clone(),
+            direccion_activa: compartido.direccion_activa.lock().unwrap().clone(),
+            semaforos_peatonales: compartido.semaforos_peatonales.lock().unwrap().clone(),
+            peatones: compartido.peatones.lock().unwrap().clone(),
+            accidentes: *compartido.contador_accidentes.lock().unwrap(),
+            atropellos: *compartido.contador_atropellos.lock().unwrap(),
+            choques,
+        }
+    }
+}
+
+// Primer registro del archivo: con qué se grabó
+#[derive(Serialize, Deserialize)]
+struct Cabecera {
+    marca: [u8; 4],
+    version: u32,
+    semilla: u64,
+    escenario: Option<String>, // Texto TOML; None es el escenario por defecto
+}
+
+// Enteros de largo variable: los cuadros ocupan bastante menos
+fn opciones() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+pub struct Grabadora {
+    escritor: BufWriter<File>,
+    fps: u64, // Ticks por segundo simulado del escenario
+}
+
+impl Grabadora {
+    pub fn crear(ruta: &Path, semilla: u64, escenario: Option<String>, fps_simulacion: u32) -> Result<Self, String> {
+        let archivo = File::create(ruta).map_err(|e| e.to_string())?;
+        let mut grabadora = Self { escritor: BufWriter::new(archivo), fps: fps_simulacion as u64 };
+        let cabecera = Cabecera { marca: MARCA, version: VERSION, semilla, escenario };
+        opciones().serialize_into(&mut grabadora.escritor, &cabecera).map_err(|e| e.to_string())?;
+        Ok(grabadora)
+    }
+
+    // Si toca guardar un cuadro en este tick: cuando el tick llega a un múltiplo
+    // de 1/CUADROS_POR_SEGUNDO s simulados, así el ritmo no depende de fps_simulacion
+    pub fn toca(&self, tick: u64) -> bool {
+        (tick * CUADROS_POR_SEGUNDO) % self.fps < CUADROS_POR_SEGUNDO
+    }
+
+    // Se vacía en cada cuadro, así el archivo sirve aunque el programa se cierre de golpe
+    pub fn grabar(&mut self, cuadro: &Cuadro) -> io::Result<()> {
+        opciones().serialize_into(&mut self.escritor, cuadro)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        self.escritor.flush()
+    }
+}
+
+// Grabación ya leída completa, para moverse libremente por ella
+pub struct Grabacion {
+    pub semilla: u64,
+    pub escenario: Escenario,
+    pub cuadros: Vec<Cuadro>,
+}
+
+impl Grabacion {
+    pub fn cargar(ruta: &Path) -> Result<Self, String> {
+        let archivo = File::open(ruta).map_err(|e| e.to_string())?;
+        let mut lector = BufReader::new(archivo);
+        let cabecera: Cabecera = opciones().deserialize_from(&mut lector)
+            .map_err(|_| "no es una grabación de la simulación".to_string())?;
+        if cabecera.marca != MARCA {
+            return Err("no es una grabación de la simulación".to_string());
+        }
+        if cabecera.version != VERSION {
+            return Err(format!("grabación de la versión {}, se esperaba la {}", cabecera.version, VERSION));
+        }
+        let escenario = match &cabecera.escenario {
+            Some(texto) => Escenario::desde_toml(texto).map_err(|e| format!("escenario de la grabación: {}", e))?,
+            None => Escenario::default(),
+        };
+
+        // Se lee hasta el final; un último cuadro cortado (el programa se cerró
+        // mientras lo escribía) se descarta
+        let mut cuadros = Vec::new();
+        loop {
+            match opciones().deserialize_from::<_, Cuadro>(&mut lector) {
+                Ok(cuadro) => cuadros.push(cuadro),
+                Err(e) => match *e {
+                    bincode::ErrorKind::Io(ref io) if io.kind() == io::ErrorKind::UnexpectedEof => break,
+                    _ => return Err(format!("cuadro {} dañado: {}", cuadros.len(), e)),
+                },
+            }
+        }
+        if cuadros.is_empty() {
+            return Err("la grabación no tiene cuadros".to_string());
+        }
+        Ok(Self { semilla: cabecera.semilla, escenario, cuadros })
+    }
+
+    pub fn duracion(&self) -> f32 {
+        self.cuadros.last().map_or(0.0, |c| c.tiempo)
+    }
+
+    // Último cuadro grabado hasta el tiempo dado
+    pub fn cuadro_en(&self, tiempo: f32) -> &Cuadro {
+        let siguiente = self.cuadros.partition_point(|c| c.tiempo <= tiempo);
+        &self.cuadros[siguiente.saturating_sub(1)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn ruta_temporal(nombre: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}_{}.sim", nombre, std::process::id()))
+    }
+
+    fn cuadro(compartido: &EstadoCompartido, tiempo: f32) -> Cuadro {
+        *compartido.contador_accidentes.lock().unwrap() = tiempo as usize;
+        Cuadro::de(compartido, tiempo)
+    }
+
+    fn bytes(cuadro: &Cuadro) -> Vec<u8> {
+        opciones().serialize(cuadro).unwrap()
+    }
+
+    // Un archivo que solo tiene la cabecera dada
+    fn escribir_cabecera(ruta: &Path, cabecera: &Cabecera) {
+        let mut archivo = File::create(ruta).unwrap();
+        opciones().serialize_into(&mut archivo, cabecera).unwrap();
+    }
+
+    #[test]
+    fn lo_grabado_se_lee_igual() {
+        let ruta = ruta_temporal("grabacion_ida_y_vuelta");
+        let texto = "duracion_verde = 12.0\nfps_simulacion = 40\n";
+        let compartido = EstadoCompartido::new(5, Escenario::desde_toml(texto).unwrap());
+        let cuadros = [cuadro(&compartido, 0.0), cuadro(&compartido, 0.05), cuadro(&compartido, 2.0)];
+
+        let mut grabadora = Grabadora::crear(&ruta, 5, Some(texto.to_string()), 40).unwrap();
+        for cuadro in &cuadros {
+            grabadora.grabar(cuadro).unwrap();
+        }
+        drop(grabadora);
+
+        let grabacion = Grabacion::cargar(&ruta).unwrap();
+        std::fs::remove_file(&ruta).unwrap();
+        assert_eq!(grabacion.semilla, 5);
+        assert_eq!(grabacion.escenario.duracion_verde, 12.0);
+        assert_eq!(grabacion.escenario.fps_simulacion, 40);
+        assert_eq!(grabacion.cuadros.len(), 3);
+        for (leido, escrito) in grabacion.cuadros.iter().zip(&cuadros) {
+            assert_eq!(bytes(leido), bytes(escrito));
+        }
+        assert_eq!(grabacion.duracion(), 2.0);
+        assert_eq!(grabacion.cuadro_en(1.0).tiempo, 0.05);
+    }
+
+    #[test]
+    fn el_ultimo_cuadro_cortado_se_descarta() {
+        let ruta = ruta_temporal("grabacion_cortada");
+        let compartido = EstadoCompartido::new(1, Escenario::default());
+        let mut grabadora = Grabadora::crear(&ruta, 1, None, 60).unwrap();
+        grabadora.grabar(&cuadro(&compartido, 0.0)).unwrap();
+        grabadora.grabar(&cuadro(&compartido, 1.0)).unwrap();
+        drop(grabadora);
+
+        let largo = std::fs::metadata(&ruta).unwrap().len();
+        File::options().write(true).open(&ruta).unwrap().set_len(largo - 3).unwrap();
+        let grabacion = Grabacion::cargar(&ruta).unwrap();
+        std::fs::remove_file(&ruta).unwrap();
+        assert_eq!(grabacion.cuadros.len(), 1);
+        assert_eq!(grabacion.duracion(), 0.0);
+    }
+
+    #[test]
+    fn se_rechaza_otra_marca() {
+        let ruta = ruta_temporal("grabacion_otra_marca");
+        escribir_cabecera(&ruta, &Cabecera { marca: *b"SIMX", version: VERSION, semilla: 1, escenario: None });
+        let error = Grabacion::cargar(&ruta).err();
+        std::fs::write(&ruta, b"esto no es una grabacion").unwrap();
+        let error_texto = Grabacion::cargar(&ruta).err();
+        std::fs::remove_file(&ruta).unwrap();
+        assert_eq!(error.as_deref(), Some("no es una grabación de la simulación"));
+        assert_eq!(error_texto.as_deref(), Some("no es una grabación de la simulación"));
+    }
+
+    #[test]
+    fn se_rechaza_otra_version() {
+        let ruta = ruta_temporal("grabacion_otra_version");
+        escribir_cabecera(&ruta, &Cabecera { marca: MARCA, version: 2, semilla: 1, escenario: None });
+        let error = Grabacion::cargar(&ruta).err();
+        std::fs::remove_file(&ruta).unwrap();
+        assert_eq!(error.as_deref(), Some("grabación de la versión 2, se esperaba la 1"));
+    }
+
+    #[test]
+    fn veinte_cuadros_por_segundo_simulado() {
+        let ruta = ruta_temporal("grabacion_ritmo");
+        let grabadora = Grabadora::crear(&ruta, 1, None, 60).unwrap();
+        let tocan: Vec<u64> = (0..12).filter(|&tick| grabadora.toca(tick)).collect();
+        // Con menos de 20 ticks por segundo, uno por tick
+        let lenta = Grabadora::crear(&ruta, 1, None, 10).unwrap();
+        std::fs::remove_file(&ruta).unwrap();
+        assert_eq!(tocan, vec![0, 3, 6, 9]);
+        assert!((0..12).all(|tick| lenta.toca(tick)));
+    }
+}
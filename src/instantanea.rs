@@ -0,0 +1,248 @@
+// instantanea.rs
+// Instantánea del estado de la simulación: vehículos, semáforos, peatones,
+// contadores, lo que recuerda cada controlador y el generador aleatorio. Desde
+// ella se puede seguir la corrida como si nunca se hubiera detenido, por ejemplo
+// para probar distintos controles de semáforos desde el mismo punto.
+use crate::escenario::{Escenario, TipoControl};
+use crate::estadisticas::Estadisticas;
+use crate::modelo::*;
+use bincode::Options;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const MARCA: [u8; 4] = *b"SIMI";
+const VERSION: u32 = 2;
+
+// Posición exacta del generador aleatorio dentro de su secuencia
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct EstadoAleatorio {
+    semilla: [u8; 32],
+    flujo: u64,
+    posicion: u128, // Palabras ya generadas
+}
+
+impl EstadoAleatorio {
+    fn de(rng: &Aleatorio) -> Self {
+        Self { semilla: rng.get_seed(), flujo: rng.get_stream(), posicion: rng.get_word_pos() }
+    }
+
+    fn generador(&self) -> Aleatorio {
+        let mut rng = Aleatorio::from_seed(self.semilla);
+        rng.set_stream(self.flujo);
+        rng.set_word_pos(self.posicion);
+        rng
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Instantanea {
+    marca: [u8; 4],
+    version: u32,
+    pub semilla: u64,
+    pub escenario: Escenario,
+    pub tick: u64, // Siguiente tick a simular
+    carros: Vec<Carro>,
+    semaforos: Vec<Semaforo>,
+    direccion_activa: Vec<Vec<Direccion>>,
+    peatones: Vec<Peaton>,
+    semaforos_peatonales: Vec<EstadoPeatonal>,
+    accidentes: usize,
+    atropellos: usize,
+    generados: usize,
+    salidas: usize,
+    choques: Vec<Choque>,
+    demora_total: f64,
+    estadisticas: Estadisticas,
+    rng: EstadoAleatorio,
+    controladores: Vec<EstadoControlador>,
+    generador: EstadoGenerador,
+}
+
+// Enteros de largo variable, igual que en las grabaciones
+fn opciones() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+impl Instantanea {
+    // Solo es coherente entre ticks, cuando la física terminó y los demás hilos
+    // esperan el siguiente: por eso la toma el motor de física
+    pub fn de(compartido: &EstadoCompartido, tick: u64) -> Self {
+        Self {
+            marca: MARCA,
+            version: VERSION,
+            semilla: compartido.semilla,
+            escenario: (*compartido.escenario).clone(),
+            tick,
+            carros: compartido.carros.lock().unwrap().clone(),
+            semaforos: compartido.semaforos.lock().unwrap().clone(),
+            direccion_activa: compartido.direccion_activa.lock().unwrap().clone(),
+            peatones: compartido.peatones.lock().unwrap().clone(),
+            semaforos_peatonales: compartido.semaforos_peatonales.lock().unwrap().clone(),
+            accidentes: *compartido.contador_accidentes.lock().unwrap(),
+            atropellos: *compartido.contador_atropellos.lock().unwrap(),
+            generados: *compartido.contador_generados.lock().unwrap(),
+            salidas: *compartido.contador_salidas.lock().unwrap(),
+            choques: compartido.choques.lock().unwrap().clone(),
+            demora_total: *compartido.demora_total.lock().unwrap(),
+            estadisticas: compartido.estadisticas.lock().unwrap().clone(),
+            rng: EstadoAleatorio::de(&compartido.rng.lock().unwrap()),
+            controladores: compartido.controladores.lock().unwrap().clone(),
+            generador: compartido.generador.lock().unwrap().clone(),
+        }
+    }
+
+    // s simulados en que se tomó
+    pub fn tiempo(&self) -> f32 {
+        self.tick as f32 * self.escenario.paso()
+    }
+
+    // Nombre por defecto según el momento, en la carpeta actual
+    pub fn ruta_por_defecto(&self) -> PathBuf {
+        PathBuf::from(format!("instantanea_{:.0}s.bin", self.tiempo()))
+    }
+
+    pub fn guardar(&self, ruta: &Path) -> Result<(), String> {
+        let archivo = File::create(ruta).map_err(|e| e.to_string())?;
+        let mut escritor = BufWriter::new(archivo);
+        opciones().serialize_into(&mut escritor, self).map_err(|e| e.to_string())?;
+        escritor.flush().map_err(|e| e.to_string())
+    }
+
+    pub fn cargar(ruta: &Path) -> Result<Self, String> {
+        let archivo = File::open(ruta).map_err(|e| e.to_string())?;
+        let mut lector = BufReader::new(archivo);
+        // La marca y la versión van primero: se revisan antes de leer el resto
+        let (marca, version): ([u8; 4], u32) = opciones().deserialize_from(&mut lector)
+            .map_err(|_| "no es una instantánea de la simulación".to_string())?;
+        if marca != MARCA {
+            return Err("no es una instantánea de la simulación".to_string());
+        }
+        if version != VERSION {
+            return Err(format!("instantánea de la versión {}, se esperaba la {}", version, VERSION));
+        }
+
+        let archivo = File::open(ruta).map_err(|e| e.to_string())?;
+        let instantanea: Self = opciones().deserialize_from(BufReader::new(archivo))
+            .map_err(|e| format!("instantánea dañada: {}", e))?;
+        if instantanea.controladores.len() != instantanea.direccion_activa.len() {
+            return Err("instantánea dañada: no coinciden los cruces".to_string());
+        }
+        Ok(instantanea)
+    }
+
+    // Estado compartido listo para lanzar los hilos, con el reloj en el tick
+    // guardado. `control` reemplaza el control de semáforos del escenario; el
+    // nuevo empieza desde la fase y etapa en que estaba cada cruce.
+    pub fn restaurar(self, control: Option<TipoControl>) -> EstadoCompartido {
+        let mut escenario = self.escenario;
+        if let Some(control) = control {
+            escenario.control = control;
+        }
+        let compartido = EstadoCompartido::new(self.semilla, escenario);
+
+        *compartido.carros.lock().unwrap() = self.carros;
+        *compartido.semaforos.lock().unwrap() = self.semaforos;
+        *compartido.direccion_activa.lock().unwrap() = self.direccion_activa;
+        *compartido.peatones.lock().unwrap() = self.peatones;
+        *compartido.semaforos_peatonales.lock().unwrap() = self.semaforos_peatonales;
+        *compartido.contador_accidentes.lock().unwrap() = self.accidentes;
+        *compartido.contador_atropellos.lock().unwrap() = self.atropellos;
+        *compartido.contador_generados.lock().unwrap() = self.generados;
+        *compartido.contador_salidas.lock().unwrap() = self.salidas;
+        *compartido.choques.lock().unwrap() = self.choques;
+        *compartido.demora_total.lock().unwrap() = self.demora_total;
+        *compartido.estadisticas.lock().unwrap() = self.estadisticas;
+        *compartido.rng.lock().unwrap() = self.rng.generador();
+        *compartido.controladores.lock().unwrap() = self.controladores;
+        *compartido.generador.lock().unwrap() = self.generador;
+        compartido.reloj.set_tick(self.tick);
+
+        compartido
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::escenario::Demanda;
+    use crate::reloj::{self, Velocidad};
+    use crate::{controlador, coordinacion};
+    use std::sync::mpsc;
+
+    // Lanza los hilos como main.rs, sin registro ni grabación, y espera a que
+    // pasen `segundos` simulados
+    fn simular(compartido: &EstadoCompartido, segundos: f32) {
+        let (emisor, receptor) = mpsc::channel();
+        compartido.reloj.set_velocidad(Velocidad::Maxima);
+        compartido.reloj.set_duracion(segundos);
+
+        let mut planes = coordinacion::planes(&compartido.escenario, &compartido.red);
+        for nodo in compartido.red.semaforizados() {
+            controlador::iniciar_semaforos(compartido.clone(), nodo, planes[nodo].take());
+        }
+        controlador::iniciar_generador_carros(emisor, compartido.clone());
+        controlador::iniciar_motor_fisica(compartido.clone(), receptor, None, None);
+        reloj::iniciar_reloj(compartido.reloj.clone());
+        compartido.reloj.esperar_fin();
+    }
+
+    // El estado completo en bytes, para comparar dos corridas
+    fn bytes(compartido: &EstadoCompartido) -> Vec<u8> {
+        opciones().serialize(&Instantanea::de(compartido, compartido.reloj.tick())).unwrap()
+    }
+
+    fn ruta_temporal(nombre: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}_{}.bin", nombre, std::process::id()))
+    }
+
+    // Corre 60 s de una vez y 30 s + 30 s pasando por una instantánea en disco:
+    // el estado final tiene que ser el mismo
+    fn seguir_es_igual_que_no_parar(escenario: Escenario, nombre: &str) {
+        let continua = EstadoCompartido::new(7, escenario.clone());
+        simular(&continua, 60.0);
+
+        let primera = EstadoCompartido::new(7, escenario);
+        simular(&primera, 30.0);
+        let ruta = ruta_temporal(nombre);
+        Instantanea::de(&primera, primera.reloj.tick()).guardar(&ruta).unwrap();
+        let cargada = Instantanea::cargar(&ruta);
+        std::fs::remove_file(&ruta).unwrap();
+        let cargada = cargada.unwrap();
+        assert_eq!(cargada.tick, primera.reloj.tick());
+
+        let segunda = cargada.restaurar(None);
+        assert_eq!(bytes(&segunda), bytes(&primera));
+        simular(&segunda, 30.0);
+
+        assert_eq!(segunda.reloj.tick(), continua.reloj.tick());
+        assert!(*continua.contador_generados.lock().unwrap() > 0);
+        assert!(bytes(&segunda) == bytes(&continua), "las corridas se separan después de la instantánea");
+    }
+
+    #[test]
+    fn seguir_con_control_fijo() {
+        seguir_es_igual_que_no_parar(Escenario::default(), "instantanea_fijo");
+    }
+
+    #[test]
+    fn seguir_con_control_adaptativo_y_demanda() {
+        let escenario = Escenario {
+            control: TipoControl::Adaptativo,
+            demanda: Some(Demanda { perfil: vec![[0.0, 0.5], [60.0, 1.5]], ..Demanda::default() }),
+            ..Escenario::default()
+        };
+        seguir_es_igual_que_no_parar(escenario, "instantanea_adaptativo");
+    }
+
+    #[test]
+    fn cargar_rechaza_otros_archivos() {
+        let ruta = ruta_temporal("no_es_instantanea");
+        std::fs::write(&ruta, b"esto no es una instantanea").unwrap();
+        let resultado = Instantanea::cargar(&ruta);
+        std::fs::remove_file(&ruta).unwrap();
+        assert_eq!(resultado.err().unwrap(), "no es una instantánea de la simulación");
+    }
+}
@@ -0,0 +1,15 @@
+// lib.rs
+// Núcleo de la simulación (modelo + controladores) sin dependencias gráficas.
+// La ventana de ggez vive en el binario (main.rs y vista.rs).
+pub mod modelo;
+pub mod colisiones;
+pub mod controlador;
+pub mod coordinacion;
+pub mod demanda;
+pub mod escenario;
+pub mod estadisticas;
+pub mod grabacion;
+pub mod instantanea;
+pub mod red;
+pub mod registro;
+pub mod reloj;
@@ -1,75 +1,252 @@
 // main.rs
-mod modelo;
-mod controlador;
+// La ventana y la repetición necesitan ggez (feature `ventana`); el modo
+// headless y --desfases funcionan sin ella.
+#[cfg(feature = "ventana")]
+mod reproduccion;
+#[cfg(feature = "ventana")]
 mod vista;
 
+use simulacion_trafico::escenario::{Escenario, TipoControl};
+use simulacion_trafico::grabacion::Grabadora;
+#[cfg(feature = "ventana")]
+use simulacion_trafico::grabacion::{Cuadro, Grabacion};
+use simulacion_trafico::instantanea::Instantanea;
+use simulacion_trafico::reloj::{self, Velocidad};
+use simulacion_trafico::red::Red;
+use simulacion_trafico::registro::Registro;
+use simulacion_trafico::{controlador, coordinacion, modelo};
+
+#[cfg(feature = "ventana")]
+use ggez::input::keyboard::{KeyCode, KeyInput};
+#[cfg(feature = "ventana")]
 use ggez::{conf, event, graphics, Context, GameResult};
-use std::sync::{mpsc, Arc, Mutex};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
 use std::time::Instant;
 
+#[cfg(feature = "ventana")]
+const AYUDA: &str = "Espacio: pausa   . : un paso   +/-: velocidad   E: estadísticas   G: guardar instantánea";
+
+#[cfg(feature = "ventana")]
 struct EstadoPrincipal {
     compartido: modelo::EstadoCompartido,
-    receptor: mpsc::Receiver<modelo::Carro>,
-    ultimo_update: Instant,
     fps_contador: usize,
     ultima_medicion_fps: Instant,
     fps_actual: usize,
+    ver_estadisticas: bool, // Panel con los viajes y los accesos (tecla E)
 }
 
+#[cfg(feature = "ventana")]
 impl EstadoPrincipal {
-    fn new() -> GameResult<Self> {
-        let (emisor, receptor) = mpsc::channel();
-
-        let semaforos = modelo::SEMAFOROS.iter().map(|(pos, dir)| modelo::Semaforo {
-            posicion: *pos,
-            estado: if *dir == "este" { modelo::EstadoSemaforo::Verde } else { modelo::EstadoSemaforo::Rojo },
-            direccion: dir.to_string(),
-        }).collect();
-
-        let compartido = modelo::EstadoCompartido {
-            carros: Arc::new(Mutex::new(Vec::with_capacity(100))),
-            semaforos: Arc::new(Mutex::new(semaforos)),
-            direccion_activa: Arc::new(Mutex::new("este".to_string())),
-            ultima_actualizacion: Arc::new(Mutex::new(Instant::now())),
-            contador_accidentes: Arc::new(Mutex::new(0)),
-        };
-
-        controlador::iniciar_semaforos(compartido.clone());
-        controlador::iniciar_generador_carros(emisor, compartido.clone());
-        controlador::iniciar_motor_fisica(compartido.clone());
+    fn new(opciones: &Opciones, compartido: modelo::EstadoCompartido, salidas: Salidas) -> GameResult<Self> {
+        let velocidad = opciones.velocidad.unwrap_or(Velocidad::Escala(1.0));
+        let (compartido, _fisica) = iniciar_simulacion(compartido, velocidad, None, salidas);
 
         Ok(Self {
             compartido,
-            receptor,
-            ultimo_update: Instant::now(),
             fps_contador: 0,
             ultima_medicion_fps: Instant::now(),
             fps_actual: 0,
+            ver_estadisticas: false,
         })
     }
 }
 
-impl event::EventHandler<ggez::GameError> for EstadoPrincipal {
-    fn update(&mut self, _ctx: &mut Context) -> GameResult {
-        // Limitar actualizaciones a 60 por segundo para ahorrar CPU
-        let ahora = Instant::now();
-        let delta = ahora.duration_since(self.ultimo_update);
+// Archivos que escribe la física mientras corre la simulación
+struct Salidas {
+    registro: Option<Registro>,
+    grabadora: Option<Grabadora>,
+}
 
-        if delta.as_millis() >= 16 { // ~60 FPS
-            // Colectar nuevos vehículos de forma eficiente
-            let mut nuevos_carros = Vec::new();
-            while let Ok(carro) = self.receptor.try_recv() {
-                nuevos_carros.push(carro);
-            }
+// Lanza el reloj y los hilos de semáforos, generador y física sobre el estado
+// compartido, recién creado o restaurado de una instantánea. Devuelve también el
+// hilo de la física, que al terminar vacía el registro.
+fn iniciar_simulacion(compartido: modelo::EstadoCompartido, velocidad: Velocidad, duracion: Option<u64>, salidas: Salidas) -> (modelo::EstadoCompartido, thread::JoinHandle<()>) {
+    let (emisor, receptor) = mpsc::channel();
+
+    compartido.reloj.set_velocidad(velocidad);
+    if let Some(segundos) = duracion {
+        compartido.reloj.set_duracion(segundos as f32);
+    }
+
+    // Un controlador por cada cruce con semáforos, con su plan si hay coordinación
+    let mut planes = coordinacion::planes(&compartido.escenario, &compartido.red);
+    for nodo in compartido.red.semaforizados() {
+        controlador::iniciar_semaforos(compartido.clone(), nodo, planes[nodo].take());
+    }
+    controlador::iniciar_generador_carros(emisor, compartido.clone());
+    let fisica = controlador::iniciar_motor_fisica(compartido.clone(), receptor, salidas.registro, salidas.grabadora);
+    reloj::iniciar_reloj(compartido.reloj.clone());
 
-            // Actualizamos solo si hay nuevos vehículos
-            if !nuevos_carros.is_empty() {
-                let mut carros = self.compartido.carros.lock().unwrap();
-                carros.extend(nuevos_carros);
+    (compartido, fisica)
+}
+
+// Opciones de línea de comandos
+struct Opciones {
+    headless: bool,
+    desfases: bool, // Solo mostrar la coordinación del corredor
+    duracion: u64, // Segundos simulados en modo headless
+    semilla: Option<u64>, // Al azar si no se da --seed
+    velocidad: Option<Velocidad>,
+    escenario: Option<PathBuf>, // Archivo TOML con los parámetros
+    control: Option<TipoControl>, // Reemplaza el control del escenario
+    registro: Option<PathBuf>,    // Archivo .csv o .ndjson con trayectorias y eventos
+    grabar: Option<PathBuf>,      // Archivo donde grabar la corrida para verla de nuevo
+    reproducir: Option<PathBuf>,  // Grabación a mostrar en vez de simular
+    cargar: Option<PathBuf>,      // Instantánea desde la que seguir la simulación
+    instantanea: Option<f32>,     // s simulados en que guardar una instantánea
+}
+
+fn leer_velocidad(valor: &str) -> Result<Velocidad, String> {
+    if valor == "max" {
+        return Ok(Velocidad::Maxima);
+    }
+
+    let escala: f32 = valor.parse()
+        .map_err(|_| format!("Velocidad inválida: {}", valor))?;
+    if !(reloj::ESCALA_MINIMA..=reloj::ESCALA_MAXIMA).contains(&escala) {
+        return Err(format!(
+            "La velocidad debe estar entre {} y {} (o \"max\")",
+            reloj::ESCALA_MINIMA, reloj::ESCALA_MAXIMA
+        ));
+    }
+
+    Ok(Velocidad::Escala(escala))
+}
+
+fn leer_opciones() -> Result<Opciones, String> {
+    let mut opciones = Opciones { headless: false, desfases: false, duracion: 60, semilla: None, velocidad: None, escenario: None, control: None, registro: None, grabar: None, reproducir: None, cargar: None, instantanea: None };
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--headless" => opciones.headless = true,
+            "--desfases" => opciones.desfases = true,
+            "--duracion" => {
+                let valor = args.next().ok_or("--duracion necesita un valor en segundos")?;
+                opciones.duracion = valor.parse()
+                    .map_err(|_| format!("Duración inválida: {}", valor))?;
+            }
+            "--seed" => {
+                let valor = args.next().ok_or("--seed necesita un número")?;
+                opciones.semilla = Some(valor.parse()
+                    .map_err(|_| format!("Semilla inválida: {}", valor))?);
             }
+            "--velocidad" => {
+                let valor = args.next().ok_or("--velocidad necesita un valor")?;
+                opciones.velocidad = Some(leer_velocidad(&valor)?);
+            }
+            "--escenario" => {
+                let valor = args.next().ok_or("--escenario necesita la ruta de un archivo TOML")?;
+                opciones.escenario = Some(PathBuf::from(valor));
+            }
+            "--control" => {
+                let valor = args.next().ok_or("--control necesita un valor")?;
+                opciones.control = Some(TipoControl::desde_nombre(&valor)
+                    .ok_or(format!("Control desconocido: {} (use fijo, actuado o adaptativo)", valor))?);
+            }
+            "--registro" => {
+                let valor = args.next().ok_or("--registro necesita la ruta de un archivo .csv o .ndjson")?;
+                opciones.registro = Some(PathBuf::from(valor));
+            }
+            "--grabar" => {
+                let valor = args.next().ok_or("--grabar necesita la ruta del archivo")?;
+                opciones.grabar = Some(PathBuf::from(valor));
+            }
+            "--reproducir" => {
+                let valor = args.next().ok_or("--reproducir necesita la ruta de una grabación")?;
+                opciones.reproducir = Some(PathBuf::from(valor));
+            }
+            "--cargar" => {
+                let valor = args.next().ok_or("--cargar necesita la ruta de una instantánea")?;
+                opciones.cargar = Some(PathBuf::from(valor));
+            }
+            "--instantanea" => {
+                let valor = args.next().ok_or("--instantanea necesita un valor en segundos")?;
+                opciones.instantanea = Some(valor.parse::<f32>().ok().filter(|s| *s >= 0.0)
+                    .ok_or(format!("Tiempo de instantánea inválido: {}", valor))?);
+            }
+            otro => return Err(format!("Opción desconocida: {}", otro)),
+        }
+    }
+
+    // El escenario ya viene dentro de la instantánea
+    if opciones.cargar.is_some() && opciones.escenario.is_some() {
+        return Err("--cargar no se puede usar con --escenario".to_string());
+    }
+    // Y la semilla también: la corrida sigue con el generador guardado
+    if opciones.cargar.is_some() && opciones.semilla.is_some() {
+        return Err("--cargar no se puede usar con --seed".to_string());
+    }
+
+    // Sin la feature ventana no hay ventana ni repetición
+    if !cfg!(feature = "ventana") && (opciones.reproducir.is_some() || !(opciones.headless || opciones.desfases)) {
+        return Err("Compilado sin la feature ventana: use --headless o --desfases".to_string());
+    }
+
+    Ok(opciones)
+}
+
+// Ejecuta la simulación sin ventana y muestra un resumen al terminar
+fn ejecutar_headless(opciones: &Opciones, compartido: modelo::EstadoCompartido, salidas: Salidas) {
+    // Sin ventana no hay nada que mirar: por defecto lo más rápido posible
+    let velocidad = opciones.velocidad.unwrap_or(Velocidad::Maxima);
+    let (compartido, fisica) = iniciar_simulacion(compartido, velocidad, Some(opciones.duracion), salidas);
+    let inicio = Instant::now();
+
+    println!("Simulando {} segundos sin ventana...", opciones.duracion);
+    compartido.reloj.esperar_fin();
+    // La física sale de su ciclo y termina de escribir el registro
+    fisica.join().unwrap();
+
+    let generados = *compartido.contador_generados.lock().unwrap();
+    let salidas = *compartido.contador_salidas.lock().unwrap();
+    let accidentes = *compartido.contador_accidentes.lock().unwrap();
+    let atropellos = *compartido.contador_atropellos.lock().unwrap();
+    let en_via = compartido.carros.lock().unwrap().len();
+    let demora = *compartido.demora_total.lock().unwrap();
+
+    println!("--- Resumen ---");
+    println!("Control de semáforos: {}", compartido.escenario.control.nombre());
+    println!("Tiempo simulado: {:.1} s (real: {:.2} s)", compartido.reloj.tiempo(), inicio.elapsed().as_secs_f32());
+    println!("Vehículos generados: {}", generados);
+    println!("Vehículos que salieron: {}", salidas);
+    println!("Vehículos en la vía: {}", en_via);
+    if compartido.escenario.demanda.is_some() {
+        let en_espera: usize = compartido.generador.lock().unwrap().en_espera.iter().sum();
+        println!("Vehículos esperando para entrar: {}", en_espera);
+    }
+    println!("Accidentes: {}", accidentes);
+    for choque in compartido.choques.lock().unwrap().iter() {
+        let [a, b] = choque.tipos;
+        println!("  {:>6.1} s en ({:.0}, {:.0}): {} con {}, {}", choque.tiempo, choque.posicion[0], choque.posicion[1], a.nombre(), b.nombre(), choque.gravedad.nombre());
+    }
+    println!("Peatones atropellados: {}", atropellos);
+    println!("Demora total: {:.0} s ({:.1} s por vehículo)", demora, demora / generados.max(1) as f64);
+    print!("{}", compartido.estadisticas.lock().unwrap().informe(compartido.reloj.tiempo()));
+}
 
-            self.ultimo_update = ahora;
+// Muestra los desfases de la coordinación y el diagrama de bandas del corredor
+fn mostrar_desfases(escenario: &Escenario) {
+    if escenario.control != TipoControl::Fijo {
+        println!("Aviso: la coordinación solo se usa con control fijo");
+    }
+    let red = Red::new(&escenario.red);
+    match coordinacion::diagrama_bandas(escenario, &red) {
+        Ok(diagrama) => print!("{}", diagrama),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
         }
+    }
+}
+
+#[cfg(feature = "ventana")]
+impl event::EventHandler<ggez::GameError> for EstadoPrincipal {
+    fn update(&mut self, _ctx: &mut Context) -> GameResult {
+        // Los vehículos nuevos los incorpora el motor de física en cada tick
+        let ahora = Instant::now();
 
         // Cálculo de FPS para diagnóstico
         self.fps_contador += 1;
@@ -86,56 +263,176 @@ impl event::EventHandler<ggez::GameError> for EstadoPrincipal {
         let mut canvas = graphics::Canvas::from_frame(ctx, graphics::Color::new(0.5, 0.7, 0.9, 1.0)); // Cambio de color de fondo a cielo
 
         // Inicializar el caché en el primer frame
-        vista::inicializar_cache(ctx)?;
+        vista::inicializar_cache(ctx, self.compartido.semilla, &self.compartido.red)?;
 
         // Dibujar capas en orden (fondo primero)
         vista::dibujar_fondo(&mut canvas, ctx)?;
         vista::dibujar_elementos_decorativos(&mut canvas, ctx)?;
-        vista::dibujar_carreteras(&mut canvas, ctx)?;
-
-        // Minimizar el tiempo que mantenemos los locks
-        let semaforos = {
-            let lock = self.compartido.semaforos.lock().unwrap();
-            lock.clone()
-        };
+        vista::dibujar_carreteras(&mut canvas, ctx, &self.compartido.red, &self.compartido.pasos)?;
 
-        for semaforo in &semaforos {
-            vista::dibujar_semaforo(&mut canvas, ctx, semaforo)?;
-        }
-
-        // Clonamos los carros para liberar el lock rápidamente
-        let carros = {
-            let lock = self.compartido.carros.lock().unwrap();
-            lock.clone()
-        };
+        let cuadro = Cuadro::de(&self.compartido, self.compartido.reloj.tiempo());
+        vista::dibujar_cuadro(&mut canvas, ctx, &cuadro, &self.compartido.pasos)?;
+        vista::dibujar_ui(&mut canvas, ctx, &cuadro, self.fps_actual, &vista::texto_velocidad(&self.compartido.reloj), AYUDA)?;
 
-        for carro in &carros {
-            vista::dibujar_vehiculo(&mut canvas, ctx, carro)?;
+        if self.ver_estadisticas {
+            let informe = self.compartido.estadisticas.lock().unwrap().informe(cuadro.tiempo);
+            vista::dibujar_estadisticas(&mut canvas, ctx, &informe)?;
         }
 
-        let direccion_activa = {
-            let lock = self.compartido.direccion_activa.lock().unwrap();
-            lock.clone()
-        };
+        canvas.finish(ctx)?;
+        Ok(())
+    }
 
-        let num_accidentes = {
-            let lock = self.compartido.contador_accidentes.lock().unwrap();
-            *lock
-        };
+    fn key_down_event(&mut self, ctx: &mut Context, input: KeyInput, _repetida: bool) -> GameResult {
+        let reloj = &self.compartido.reloj;
 
-        vista::dibujar_ui(&mut canvas, ctx, carros.len(), &direccion_activa, self.fps_actual, num_accidentes)?;
+        match input.keycode {
+            Some(KeyCode::Space) => reloj.alternar_pausa(),
+            Some(KeyCode::Period) | Some(KeyCode::NumpadDecimal) => reloj.avanzar_un_paso(),
+            Some(KeyCode::Plus) | Some(KeyCode::Equals) | Some(KeyCode::NumpadAdd) => reloj.acelerar(),
+            Some(KeyCode::Minus) | Some(KeyCode::NumpadSubtract) => reloj.desacelerar(),
+            Some(KeyCode::E) => self.ver_estadisticas = !self.ver_estadisticas,
+            Some(KeyCode::G) => {
+                // La física la guarda al terminar el tick; en pausa se avanza uno
+                *self.compartido.instantanea_pedida.lock().unwrap() = Some(reloj.tiempo());
+                reloj.avanzar_un_paso();
+            }
+            Some(KeyCode::Escape) => ctx.request_quit(),
+            _ => {}
+        }
 
-        canvas.finish(ctx)?;
         Ok(())
     }
 }
 
-fn main() -> GameResult {
+// Abre la ventana de repetición; solo vuelve si no se pudo crear
+#[cfg(feature = "ventana")]
+fn abrir_repeticion(grabacion: Grabacion) -> GameResult {
+    let [ancho, alto] = grabacion.escenario.red.dimensiones();
+    let (ctx, event_loop) = ggez::ContextBuilder::new("simulacion-trafico", "rust")
+        .window_setup(conf::WindowSetup::default().title("Simulación de Tráfico (repetición)"))
+        .window_mode(conf::WindowMode::default().dimensions(ancho, alto))
+        .build()?;
+    event::run(ctx, event_loop, reproduccion::Reproduccion::new(grabacion))
+}
+
+// Abre la ventana de la simulación; solo vuelve si no se pudo crear
+#[cfg(feature = "ventana")]
+fn abrir_ventana(opciones: &Opciones, compartido: modelo::EstadoCompartido, salidas: Salidas) -> GameResult {
+    // La ventana muestra toda la red
+    let [ancho, alto] = compartido.escenario.red.dimensiones();
     let (ctx, event_loop) = ggez::ContextBuilder::new("simulacion-trafico", "rust")
         .window_setup(conf::WindowSetup::default().title("Simulación de Tráfico"))
-        .window_mode(conf::WindowMode::default().dimensions(600.0, 600.0))
+        .window_mode(conf::WindowMode::default().dimensions(ancho, alto))
         .build()?;
 
-    let estado = EstadoPrincipal::new()?;
+    let estado = EstadoPrincipal::new(opciones, compartido, salidas)?;
     event::run(ctx, event_loop, estado)
+}
+
+fn main() {
+    let opciones = match leer_opciones() {
+        Ok(opciones) => opciones,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Uso: simulacion [--headless] [--duracion SEGUNDOS] [--seed N] [--velocidad X|max] [--escenario ARCHIVO.toml] [--control fijo|actuado|adaptativo] [--desfases] [--registro ARCHIVO.csv|.ndjson] [--grabar ARCHIVO] [--reproducir ARCHIVO] [--cargar ARCHIVO] [--instantanea SEGUNDOS]");
+            std::process::exit(2);
+        }
+    };
+
+    #[cfg(feature = "ventana")]
+    if let Some(ruta) = &opciones.reproducir {
+        let grabacion = match Grabacion::cargar(ruta) {
+            Ok(grabacion) => grabacion,
+            Err(e) => {
+                eprintln!("{}: {}", ruta.display(), e);
+                std::process::exit(2);
+            }
+        };
+        if let Err(e) = abrir_repeticion(grabacion) {
+            eprintln!("No se pudo abrir la ventana: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // El estado inicial sale del escenario o de una instantánea. La grabación
+    // guarda el texto del escenario para reconstruir la red al verla.
+    let (compartido, texto_escenario) = match &opciones.cargar {
+        Some(ruta) => {
+            let instantanea = match Instantanea::cargar(ruta) {
+                Ok(instantanea) => instantanea,
+                Err(e) => {
+                    eprintln!("{}: {}", ruta.display(), e);
+                    std::process::exit(2);
+                }
+            };
+            println!("Siguiendo desde los {:.1} s de {} (semilla {})", instantanea.tiempo(), ruta.display(), instantanea.semilla);
+            let texto = toml::to_string(&instantanea.escenario).map_err(|e| e.to_string());
+            (instantanea.restaurar(opciones.control), texto.map(Some))
+        }
+        None => {
+            let mut escenario = match &opciones.escenario {
+                Some(ruta) => match Escenario::cargar(ruta) {
+                    Ok(escenario) => escenario,
+                    Err(e) => {
+                        eprintln!("{}: {}", ruta.display(), e);
+                        std::process::exit(2);
+                    }
+                },
+                None => Escenario::default(),
+            };
+            if let Some(control) = opciones.control {
+                escenario.control = control;
+            }
+            let texto = opciones.escenario.as_ref().map(std::fs::read_to_string).transpose();
+            (modelo::EstadoCompartido::new(opciones.semilla.unwrap_or_else(rand::random), escenario), texto.map_err(|e| e.to_string()))
+        }
+    };
+    *compartido.instantanea_pedida.lock().unwrap() = opciones.instantanea;
+
+    if opciones.desfases {
+        mostrar_desfases(&compartido.escenario);
+        return;
+    }
+    if opciones.cargar.is_none() {
+        println!("Semilla: {} (use --seed {} para repetir esta corrida)", compartido.semilla, compartido.semilla);
+    }
+
+    let registro = match &opciones.registro {
+        Some(ruta) => match Registro::crear(ruta) {
+            Ok(registro) => Some(registro),
+            Err(e) => {
+                eprintln!("{}: {}", ruta.display(), e);
+                std::process::exit(2);
+            }
+        },
+        None => None,
+    };
+
+    let grabadora = match &opciones.grabar {
+        Some(ruta) => {
+            match texto_escenario.and_then(|texto| Grabadora::crear(ruta, compartido.semilla, texto, compartido.escenario.fps_simulacion)) {
+                Ok(grabadora) => Some(grabadora),
+                Err(e) => {
+                    eprintln!("{}: {}", ruta.display(), e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        None => None,
+    };
+    let salidas = Salidas { registro, grabadora };
+
+    #[cfg(feature = "ventana")]
+    if !opciones.headless {
+        if let Err(e) = abrir_ventana(&opciones, compartido, salidas) {
+            eprintln!("No se pudo abrir la ventana: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Sin la feature ventana leer_opciones ya exigió --headless
+    ejecutar_headless(&opciones, compartido, salidas);
 }
\ No newline at end of file
@@ -1,73 +1,777 @@
 // modelo.rs
-use ggez::graphics;
+use crate::demanda;
+use crate::escenario::Escenario;
+use crate::estadisticas::Estadisticas;
+use crate::red::Red;
+use crate::reloj::Reloj;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use rand_chacha::ChaCha8Rng;
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
 
-// Configuración constante
-pub const VIA_HORIZONTAL: graphics::Rect = graphics::Rect::new(0.0, 300.0, 600.0, 50.0);
-pub const VIA_VERTICAL: graphics::Rect = graphics::Rect::new(300.0, 0.0, 50.0, 600.0);
-pub const COLOR_ASFALTO: graphics::Color = graphics::Color::new(0.2, 0.2, 0.2, 1.0);
-pub const COLOR_LINEA_CENTRAL: graphics::Color = graphics::Color::new(1.0, 1.0, 0.0, 1.0);
-pub const COLOR_FONDO: graphics::Color = graphics::Color::new(0.5, 0.7, 0.9, 1.0); // Cielo azul
+// Generador aleatorio de la simulación. ChaCha8 da la misma secuencia en
+// cualquier plataforma para una misma semilla.
+pub type Aleatorio = ChaCha8Rng;
 
-pub const POSICION_SEMAFORO_VERTICAL: f32 = 250.0;
-pub const POSICION_SEMAFORO_HORIZONTAL: f32 = 360.0;
+// Tipos geométricos propios, sin depender de la librería gráfica.
+// vista.rs los convierte a tipos de ggez al momento de dibujar.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+    pub const WHITE: Color = Color::new(1.0, 1.0, 1.0, 1.0);
+
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub fn from_rgb(r: u8, g: u8, b: u8) -> Self {
+        Self::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
 
+impl Rect {
+    pub const fn new(x: f32, y: f32, w: f32, h: f32) -> Self {
+        Self { x, y, w, h }
+    }
+
+    pub fn contiene(&self, punto: [f32; 2]) -> bool {
+        punto[0] >= self.x && punto[0] <= self.x + self.w &&
+            punto[1] >= self.y && punto[1] <= self.y + self.h
+    }
+}
+
+// Configuración constante (valores por defecto del escenario, ver escenario.rs)
+pub const COLOR_ASFALTO: Color = Color::new(0.2, 0.2, 0.2, 1.0);
+pub const COLOR_LINEA_CENTRAL: Color = Color::new(1.0, 1.0, 0.0, 1.0);
+pub const COLOR_FONDO: Color = Color::new(0.5, 0.7, 0.9, 1.0); // Cielo azul
+
+// Líneas de parada de los accesos este (x) y norte (y), justo antes de los pasos de
+// peatones. Las de oeste y sur son su reflejo respecto al centro de la intersección.
+pub const POSICION_SEMAFORO_VERTICAL: f32 = 270.0;
+pub const POSICION_SEMAFORO_HORIZONTAL: f32 = 370.0;
+
+pub const CENTRO_INTERSECCION: [f32; 2] = [325.0, 325.0];
+pub const MEDIA_CAJA: f32 = 25.0;            // También es la mitad del ancho de una vía de un carril por sentido
+pub const ANCHO_CARRIL: f32 = 25.0;
+pub const DESPLAZAMIENTO_CARRIL: f32 = 12.5; // Del centro de la vía al centro del primer carril
+pub const MAXIMO_CARRILES: usize = 4;        // Por sentido
+
+// Red por defecto: un cruce en el centro de la ventana con una vía hacia cada
+// borde. Los vehículos aparecen en los extremos y se circula por la derecha.
+pub const NODOS_RED: [[f32; 2]; 5] = [
+    CENTRO_INTERSECCION,
+    [0.0, 325.0],   // Extremo oeste: de aquí salen los que van al este
+    [325.0, 600.0], // Extremo sur
+    [600.0, 325.0], // Extremo este
+    [325.0, 0.0],   // Extremo norte
+];
+pub const VIAS_RED: [[usize; 2]; 4] = [[1, 0], [2, 0], [3, 0], [4, 0]];
+pub const CARRILES_POR_SENTIDO: usize = 1;
 
-pub const PUNTOS_APARICION: [(&str, [f32; 2]); 2] = [
-    ("este", [0.0, 326.0]),     // Carril este
-    ("norte", [320.0, 570.0]),  // Carril norte
+// Cada semáforo queda a la derecha de su carril, antes de la intersección. Las
+// posiciones son las del cruce en CENTRO_INTERSECCION; en otros cruces se trasladan.
+pub const SEMAFOROS: [([f32; 2], Direccion); 4] = [
+    ([280.0, 365.0], Direccion::Este),   // Semáforo este
+    ([365.0, 370.0], Direccion::Norte),  // Semáforo norte
+    ([370.0, 285.0], Direccion::Oeste),  // Semáforo oeste
+    ([285.0, 280.0], Direccion::Sur),    // Semáforo sur
 ];
 
-pub const SEMAFOROS: [([f32; 2], &str); 2] = [
-    ([270.0, 310.0], "este"),   // Semáforo este
-    ([310.0, 370.0], "norte"),  // Semáforo norte
+// Direcciones que tienen verde al mismo tiempo
+pub const FASES: [&[Direccion]; 2] = [
+    &[Direccion::Este, Direccion::Oeste],
+    &[Direccion::Norte, Direccion::Sur],
 ];
 
+// Proporción de vehículos que giran en la intersección; el resto sigue recto
+pub const PROPORCION_GIRO_IZQUIERDA: f32 = 0.2;
+pub const PROPORCION_GIRO_DERECHA: f32 = 0.2;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direccion {
+    Este,
+    Norte,
+    Oeste,
+    Sur,
+}
+
+impl Direccion {
+    pub const TODAS: [Direccion; 4] = [Direccion::Este, Direccion::Norte, Direccion::Oeste, Direccion::Sur];
+
+    // Posición en arreglos indexados por dirección
+    pub fn indice(self) -> usize {
+        self as usize
+    }
+
+    pub fn nombre(self) -> &'static str {
+        match self {
+            Direccion::Este => "este",
+            Direccion::Norte => "norte",
+            Direccion::Oeste => "oeste",
+            Direccion::Sur => "sur",
+        }
+    }
+
+    // Vector unitario de avance en coordenadas de pantalla (y crece hacia abajo)
+    pub fn vector(self) -> [f32; 2] {
+        match self {
+            Direccion::Este => [1.0, 0.0],
+            Direccion::Norte => [0.0, -1.0],
+            Direccion::Oeste => [-1.0, 0.0],
+            Direccion::Sur => [0.0, 1.0],
+        }
+    }
+
+    pub fn opuesta(self) -> Direccion {
+        self.salida(Movimiento::Izquierda).salida(Movimiento::Izquierda)
+    }
+
+    pub fn angulo(self) -> f32 {
+        let [x, y] = self.vector();
+        y.atan2(x)
+    }
+
+    // Este y oeste circulan por la vía horizontal; norte y sur por la vertical
+    pub fn eje_horizontal(self) -> bool {
+        matches!(self, Direccion::Este | Direccion::Oeste)
+    }
+
+    // Avance de un punto a lo largo de esta dirección
+    pub fn avance(self, punto: [f32; 2]) -> f32 {
+        let [x, y] = self.vector();
+        punto[0] * x + punto[1] * y
+    }
+
+    // Dirección en la que sale un vehículo según su movimiento
+    pub fn salida(self, movimiento: Movimiento) -> Direccion {
+        let [x, y] = self.vector();
+        let vector = match movimiento {
+            Movimiento::Recto => return self,
+            Movimiento::Derecha => [-y, x],
+            Movimiento::Izquierda => [y, -x],
+        };
+        *Direccion::TODAS.iter().find(|d| d.vector() == vector).unwrap()
+    }
+}
+
+impl std::fmt::Display for Direccion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.nombre())
+    }
+}
+
 // Parámetros de simulación (ajustados para mejor rendimiento)
 pub const VELOCIDAD_VEHICULO: i32 = 40;
 pub const INTERVALO_APARICION: u64 = 3;
+pub const FLUJO_POR_ACCESO: f32 = 240.0; // veh/h de cada extremo con [demanda]; el promedio de INTERVALO_APARICION en el cruce por defecto
 pub const DURACION_VERDE: u64 = 10;
 pub const DURACION_AMARILLO: u64 = 2;
-pub const FPS_SIMULACION: u64 = 120;
+pub const DURACION_TODO_ROJO: f32 = 1.0; // s para que se vacíe la intersección entre fases
+pub const FPS_SIMULACION: u64 = 120; // Ticks por segundo simulado
+
+// Control actuado por detectores
+pub const VERDE_MINIMO: f32 = 5.0;     // s de verde antes de poder cambiar
+pub const VERDE_MAXIMO: f32 = 30.0;    // s de verde como máximo si otros esperan
+pub const BRECHA_DETECTOR: f32 = 2.0;  // s sin detecciones para cortar el verde
+pub const LARGO_DETECTOR: f32 = 80.0;  // px de carril que cubre cada detector
+
+// Control adaptativo (Webster)
+pub const CICLO_MINIMO: f32 = 16.0;          // s
+pub const CICLO_MAXIMO: f32 = 120.0;         // s
+pub const FLUJO_SATURACION: f32 = 0.45;      // Vehículos por segundo de verde que descarga un carril
+pub const DISTANCIA_CONTADOR: f32 = 150.0;   // px antes de la línea donde se cuentan las llegadas
+pub const VELOCIDAD_DETENIDO: f32 = 2.0;     // px/s por debajo de los cuales un vehículo está en cola
+pub const VELOCIDAD_REANUDA: f32 = 10.0;     // px/s desde los que un detenido vuelve a estar en marcha (y puede contar otra parada)
+
+// Modelo de seguimiento (Intelligent Driver Model)
+pub const DISTANCIA_MINIMA: f32 = 8.0;      // px que se dejan con el de adelante estando detenidos
+pub const TIEMPO_SEGUIMIENTO: f32 = 1.2;    // s de separación deseada a velocidad constante
+pub const EXPONENTE_ACELERACION: i32 = 4;
+pub const FACTOR_FRENADO_MAXIMO: f32 = 2.5; // Veces el frenado cómodo que se acepta ante un rojo
+
+// Cambios de carril (MOBIL)
+pub const CORTESIA: f32 = 0.3;                  // Peso de lo que pierden los demás al cambiarse
+pub const UMBRAL_CAMBIO_CARRIL: f32 = 1.0;      // px/s² de ganancia mínima para cambiarse
+pub const SESGO_DERECHA: f32 = 0.5;             // px/s² a favor de volver hacia la acera
+pub const SESGO_GIRO: f32 = 6.0;                // px/s² a favor del carril del giro que viene
+pub const DISTANCIA_PREPARAR_GIRO: f32 = 200.0; // px antes del cruce desde los que se busca ese carril
+pub const AVANCE_CAMBIO: f32 = 4.0;             // px hacia adelante por cada px hacia el costado
+
+// Vehículos de emergencia
+pub const PROBABILIDAD_EMERGENCIA: f32 = 0.02; // De que un vehículo generado sea de emergencia
+pub const DISTANCIA_PREFERENCIA: f32 = 250.0;  // px antes de la línea desde los que piden verde
+pub const DISTANCIA_CEDER: f32 = 120.0;        // px detrás de un vehículo desde los que este se orilla
+pub const DESPLAZAMIENTO_ORILLA: f32 = 6.0;    // px que se corre hacia la orilla al ceder el paso
+
+// Choques
+pub const DURACION_MARCA_CHOQUE: f32 = 3.0;      // s que se ve la marca del punto de contacto
+pub const VELOCIDAD_CHOQUE_MODERADO: f32 = 15.0; // px/s de velocidad relativa desde la que el choque es moderado
+pub const VELOCIDAD_CHOQUE_GRAVE: f32 = 35.0;    // px/s desde los que es grave
+pub const AUXILIO_EN_CHOQUES: bool = true;       // La ambulancia de cada choque va hasta él
+pub const DISTANCIA_AUXILIO: f32 = 120.0;        // px del siniestro a los que llega la ambulancia para atenderlo
+pub const TIEMPO_AUXILIO: f32 = 8.0;             // s que tarda en despejarlo una vez que llega
+
+// Peatones
+pub const INTERVALO_PEATONES: f32 = 5.0; // s entre apariciones de peatones
+pub const VELOCIDAD_PEATON: f32 = 10.0;  // px/s
+pub const RADIO_PEATON: f32 = 3.0;
+pub const ANCHO_ACERA: f32 = 10.0;
+pub const ANCHO_PASO: f32 = 20.0;        // Largo de la franja del paso de peatones a lo largo de la vía
+pub const MARGEN_PASO: f32 = 30.0;       // Un peatón no baja de la acera si hay un vehículo a menos de esto del paso
+
+// Etapa de la fase actual de un cruce
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Etapa {
+    Verde,
+    Amarillo,
+    TodoRojo,
+}
+
+// Lo que recuerda un controlador de semáforos y no se deduce del escenario; con
+// esto una instantánea retoma cada cruce donde quedó
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EstadoControlador {
+    pub fase: usize,  // Índice en escenario.fases
+    pub etapa: Etapa,
+    pub inicio: f32,  // s simulados en que empezó la etapa
+    pub memoria: MemoriaControl,
+}
+
+impl Default for EstadoControlador {
+    // La primera fase del escenario en verde desde el inicio
+    fn default() -> Self {
+        Self { fase: 0, etapa: Etapa::Verde, inicio: 0.0, memoria: MemoriaControl::Ninguna }
+    }
+}
+
+// Lo propio de cada tipo de control
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum MemoriaControl {
+    Ninguna, // Control fijo, o un cruce que todavía no empezó
+    Actuado {
+        ultima_deteccion: f32,
+    },
+    Adaptativo {
+        ocupados: Vec<bool>, // Cada contador, en el tick anterior
+        llegadas: [usize; Direccion::TODAS.len()],
+        colas: [usize; Direccion::TODAS.len()],
+        flujo: [f32; Direccion::TODAS.len()],
+        verdes: Vec<f32>,
+        inicio_ciclo: f32,
+    },
+}
+
+// Lo que recuerda el generador de vehículos y peatones entre ticks
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EstadoGenerador {
+    pub proxima_aparicion: f32, // s simulados; sin [demanda]
+    pub proximas_llegadas: Vec<f32>, // Con [demanda], una por entrada de la red
+    pub en_espera: Vec<usize>,  // Vehículos que llegaron y no pudieron entrar, por entrada
+    pub proximo_peaton: f32,
+    pub choques_vistos: usize,
+    pub ambulancias_pendientes: VecDeque<Option<(usize, usize)>>, // Ruta de cada una, con auxilio
+}
+
+impl EstadoGenerador {
+    pub fn new(escenario: &Escenario, red: &Red, rng: &mut Aleatorio) -> Self {
+        let llegadas = demanda::llegadas(escenario, red).unwrap_or_default();
+        Self {
+            proxima_aparicion: escenario.intervalo_aparicion,
+            proximas_llegadas: llegadas.iter().map(|l| l.siguiente(rng, 0.0)).collect(),
+            en_espera: vec![0; llegadas.len()],
+            proximo_peaton: escenario.intervalo_peatones,
+            choques_vistos: 0,
+            ambulancias_pendientes: VecDeque::new(),
+        }
+    }
+}
 
 // Estado compartido
 #[derive(Clone)]
 pub struct EstadoCompartido {
     pub carros: Arc<Mutex<Vec<Carro>>>,
     pub semaforos: Arc<Mutex<Vec<Semaforo>>>,
-    pub direccion_activa: Arc<Mutex<String>>,
-    pub ultima_actualizacion: Arc<Mutex<Instant>>,
+    pub direccion_activa: Arc<Mutex<Vec<Vec<Direccion>>>>, // Direcciones de la fase en verde, por nodo de la red
     pub contador_accidentes: Arc<Mutex<usize>>,
+    pub choques: Arc<Mutex<Vec<Choque>>>, // Todos los choques de la corrida, en orden
+    pub peatones: Arc<Mutex<Vec<Peaton>>>,
+    pub pasos: Arc<Vec<PasoPeatonal>>, // Uno por cada acceso con semáforo
+    pub semaforos_peatonales: Arc<Mutex<Vec<EstadoPeatonal>>>, // Índice igual al de `pasos`
+    pub contador_atropellos: Arc<Mutex<usize>>,
+    pub contador_generados: Arc<Mutex<usize>>,
+    pub contador_salidas: Arc<Mutex<usize>>,
+    pub demora_total: Arc<Mutex<f64>>, // Segundos perdidos respecto a ir a la velocidad deseada, sumando todos los vehículos
+    pub estadisticas: Arc<Mutex<Estadisticas>>, // Viajes terminados y contadores por acceso
+    pub controladores: Arc<Mutex<Vec<EstadoControlador>>>, // Por nodo; cada hilo de semáforos publica el suyo en cada tick
+    pub generador: Arc<Mutex<EstadoGenerador>>,           // Lo publica el generador en cada tick
+    pub instantanea_pedida: Arc<Mutex<Option<f32>>>,      // s simulados desde los que la física guarda una instantánea
+    pub semilla: u64,
+    pub rng: Arc<Mutex<Aleatorio>>, // Único generador aleatorio de los controladores
+    pub reloj: Arc<Reloj>,
+    pub escenario: Arc<Escenario>,
+    pub red: Arc<Red>,
+}
+
+impl EstadoCompartido {
+    pub fn new(semilla: u64, escenario: Escenario) -> Self {
+        let red = Red::new(&escenario.red);
+
+        // Un semáforo por cada acceso de los cruces semaforizados. En todos, la
+        // primera fase del escenario empieza en verde.
+        let fase_inicial = &escenario.fases[0].direcciones;
+        let mut semaforos = Vec::new();
+        let mut direccion_inicial = vec![Vec::new(); red.nodos.len()];
+        for nodo in red.semaforizados() {
+            for &enlace in &red.nodos[nodo].entrantes {
+                let direccion = red.enlaces[enlace].direccion;
+                // validar() garantiza que hay semáforo para cada acceso
+                let posicion = escenario.posicion_semaforo(direccion, red.nodos[nodo].posicion).unwrap();
+                semaforos.push(Semaforo {
+                    posicion: red.ajustar_al_ancho(enlace, posicion),
+                    estado: if fase_inicial.contains(&direccion) { EstadoSemaforo::Verde } else { EstadoSemaforo::Rojo },
+                    direccion,
+                    nodo,
+                    enlace,
+                });
+            }
+            direccion_inicial[nodo] = fase_inicial.clone();
+        }
+        let pasos: Vec<PasoPeatonal> = semaforos.iter()
+            .map(|s| PasoPeatonal::new(&red, s.enlace, &escenario))
+            .collect();
+        let semaforos_peatonales = pasos.iter()
+            .map(|paso| if paso.compatible(fase_inicial) { EstadoPeatonal::Pasar } else { EstadoPeatonal::NoPasar })
+            .collect();
+
+        let estadisticas = Estadisticas::new(&semaforos, red.enlaces.len());
+        let controladores = vec![EstadoControlador::default(); red.nodos.len()];
+        // Con [demanda], la primera llegada de cada entrada ya sale de la semilla
+        let mut rng = Aleatorio::seed_from_u64(semilla);
+        let generador = EstadoGenerador::new(&escenario, &red, &mut rng);
+
+        Self {
+            carros: Arc::new(Mutex::new(Vec::with_capacity(100))),
+            semaforos: Arc::new(Mutex::new(semaforos)),
+            direccion_activa: Arc::new(Mutex::new(direccion_inicial)),
+            contador_accidentes: Arc::new(Mutex::new(0)),
+            choques: Arc::new(Mutex::new(Vec::new())),
+            peatones: Arc::new(Mutex::new(Vec::new())),
+            pasos: Arc::new(pasos),
+            semaforos_peatonales: Arc::new(Mutex::new(semaforos_peatonales)),
+            contador_atropellos: Arc::new(Mutex::new(0)),
+            contador_generados: Arc::new(Mutex::new(0)),
+            contador_salidas: Arc::new(Mutex::new(0)),
+            demora_total: Arc::new(Mutex::new(0.0)),
+            estadisticas: Arc::new(Mutex::new(estadisticas)),
+            controladores: Arc::new(Mutex::new(controladores)),
+            generador: Arc::new(Mutex::new(generador)),
+            instantanea_pedida: Arc::new(Mutex::new(None)),
+            semilla,
+            rng: Arc::new(Mutex::new(rng)),
+            // Un hilo de semáforos por cruce, más el generador y la física
+            reloj: Arc::new(Reloj::new(escenario.paso(), red.semaforizados().len() + 2)),
+            escenario: Arc::new(escenario),
+            red: Arc::new(red),
+        }
+    }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct Carro {
-    pub posicion: [f32; 2],
-    pub direccion: &'static str,
-    pub color: graphics::Color,
-    pub velocidad: f32,
+    pub id: usize,          // Orden en que fue generado
+    pub aparicion: f32,     // s simulados en que entró a la red
+    pub salida: Option<f32>, // s simulados en que salió por un extremo
+    pub origen: usize,      // Enlace por el que entró
+    pub posicion: [f32; 2], // Centro del vehículo
+    pub angulo: f32,        // Rumbo en radianes (0 = este, y hacia abajo)
+    pub direccion: Direccion,
+    pub enlace: usize,               // Enlace de la red por el que va; al girar cambia cuando termina la curva
+    pub carril: usize,               // Carril del enlace; al cambiarse se desliza hacia él mientras avanza
+    pub destino: usize,              // Nodo extremo de la red donde sale
+    pub movimiento: Movimiento,      // Lo que hace al final del enlace
+    pub recorrido_giro: Option<f32>, // Distancia recorrida dentro de la curva
+    pub color: Color,
+    pub velocidad: f32,          // px/s actuales
+    pub velocidad_deseada: f32,  // px/s a los que iría con la vía libre
     pub tipo: TipoVehiculo,
     pub loco: bool,
+    pub orillado: bool, // Detenido a un lado para dejar pasar a un vehículo de emergencia
+    pub siniestro: Option<Siniestro>, // Chocado: queda detenido en la vía hasta que lo retiran
+    pub demora: f32,   // s perdidos en su viaje respecto a ir a la velocidad deseada
+    pub paradas: u32,  // Veces que se detuvo
+    pub detenido: bool,
 }
 
-#[derive(Clone)]
+// Vehículo chocado que bloquea la vía
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Siniestro {
+    pub gravedad: Gravedad,
+    pub retiro: f32, // s simulados en que deja la vía
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Gravedad {
+    Leve,
+    Moderado,
+    Grave,
+}
+
+impl Gravedad {
+    // Según la velocidad relativa de los dos vehículos al chocar, en px/s
+    pub fn por_velocidad(velocidad_relativa: f32) -> Self {
+        if velocidad_relativa >= VELOCIDAD_CHOQUE_GRAVE {
+            Gravedad::Grave
+        } else if velocidad_relativa >= VELOCIDAD_CHOQUE_MODERADO {
+            Gravedad::Moderado
+        } else {
+            Gravedad::Leve
+        }
+    }
+
+    // s que pasan hasta que se retiran los vehículos si no llega auxilio
+    pub fn tiempo_despeje(self) -> f32 {
+        match self {
+            Gravedad::Leve => 20.0,
+            Gravedad::Moderado => 45.0,
+            Gravedad::Grave => 90.0,
+        }
+    }
+
+    pub fn nombre(self) -> &'static str {
+        match self {
+            Gravedad::Leve => "leve",
+            Gravedad::Moderado => "moderado",
+            Gravedad::Grave => "grave",
+        }
+    }
+}
+
+// Detector de lazo virtual: un tramo del carril antes de la línea de parada,
+// ocupado si algún vehículo que todavía no llega a la intersección lo pisa
+pub struct Detector {
+    pub enlace: usize,
+    pub direccion: Direccion,
+    pub inicio: f32, // Avance donde empieza el tramo
+    pub fin: f32,    // Avance donde termina
+    linea: f32,      // Avance de la línea de parada
+}
+
+impl Detector {
+    // Tramo de `largo` px que termina `distancia` px antes de la línea de parada del enlace
+    pub fn new(red: &Red, enlace: usize, escenario: &Escenario, distancia: f32, largo: f32) -> Self {
+        let linea = red.linea_parada(enlace, escenario);
+        let fin = linea - distancia;
+        Self { enlace, direccion: red.enlaces[enlace].direccion, inicio: fin - largo, fin, linea }
+    }
+
+    fn en_acceso<'a>(&self, carros: &'a [Carro]) -> impl Iterator<Item = &'a Carro> {
+        let enlace = self.enlace;
+        carros.iter().filter(move |carro| carro.enlace == enlace && carro.recorrido_giro.is_none())
+    }
+
+    pub fn ocupado(&self, carros: &[Carro]) -> bool {
+        self.en_acceso(carros).any(|carro| {
+            let centro = self.direccion.avance(carro.posicion);
+            let medio_largo = carro.tipo.dimensiones()[0] / 2.0;
+            centro + medio_largo >= self.inicio && centro - medio_largo <= self.fin
+        })
+    }
+
+    // Vehículos detenidos antes de la línea de parada, en todo el acceso y no solo en el tramo
+    pub fn cola(&self, carros: &[Carro]) -> usize {
+        self.en_acceso(carros)
+            .filter(|carro| carro.velocidad < VELOCIDAD_DETENIDO)
+            .filter(|carro| self.direccion.avance(carro.posicion) + carro.tipo.dimensiones()[0] / 2.0 <= self.linea)
+            .count()
+    }
+}
+
+// Paso de peatones pintado entre la línea de parada de un acceso y la
+// intersección. Lo cruzan los carriles de ese acceso y los de salida opuestos.
+pub struct PasoPeatonal {
+    pub nodo: usize, // Cruce de la red al que pertenece
+    pub acceso: Direccion,
+    pub zona: Rect,     // Franja de acera a acera
+    pub media_via: f32, // Del eje de la vía al borde de la calzada
+}
+
+impl PasoPeatonal {
+    // Paso en la llegada del enlace a su cruce
+    pub fn new(red: &Red, enlace: usize, escenario: &Escenario) -> Self {
+        let acceso = red.enlaces[enlace].direccion;
+        let linea = red.linea_parada(enlace, escenario);
+        let [x, y] = acceso.vector();
+        // Extremos de la franja a lo largo de la vía, en coordenadas de pantalla
+        let a = linea * (x + y);
+        let b = (linea + ANCHO_PASO) * (x + y);
+        let (desde, hasta) = (a.min(b), a.max(b));
+
+        let [cx, cy] = red.fin(enlace);
+        let media_via = red.media_via(enlace);
+        let ancho = 2.0 * (media_via + ANCHO_ACERA);
+        let zona = if acceso.eje_horizontal() {
+            Rect::new(desde, cy - ancho / 2.0, hasta - desde, ancho)
+        } else {
+            Rect::new(cx - ancho / 2.0, desde, ancho, hasta - desde)
+        };
+        Self { nodo: red.enlaces[enlace].hasta, acceso, zona, media_via }
+    }
+
+    // Puntos sobre las dos aceras donde empieza y termina el cruce
+    pub fn extremos(&self) -> [[f32; 2]; 2] {
+        let z = &self.zona;
+        if self.acceso.eje_horizontal() {
+            let x = z.x + z.w / 2.0;
+            [[x, z.y + ANCHO_ACERA / 2.0], [x, z.y + z.h - ANCHO_ACERA / 2.0]]
+        } else {
+            let y = z.y + z.h / 2.0;
+            [[z.x + ANCHO_ACERA / 2.0, y], [z.x + z.w - ANCHO_ACERA / 2.0, y]]
+        }
+    }
+
+    // Si los vehículos que van en esta dirección pasan por el paso
+    pub fn cruza(&self, direccion: Direccion) -> bool {
+        direccion.eje_horizontal() == self.acceso.eje_horizontal()
+    }
+
+    // Avance de los bordes de la franja para un vehículo que va en `direccion`
+    pub fn tramo(&self, direccion: Direccion) -> (f32, f32) {
+        let z = &self.zona;
+        let a = direccion.avance([z.x, z.y]);
+        let b = direccion.avance([z.x + z.w, z.y + z.h]);
+        (a.min(b), a.max(b))
+    }
+
+    // Si el punto está sobre la calzada, fuera de las aceras
+    pub fn sobre_calzada(&self, punto: [f32; 2]) -> bool {
+        let z = &self.zona;
+        // Distancia al eje de la vía, que pasa por el medio de la franja
+        let al_eje = if self.acceso.eje_horizontal() {
+            punto[1] - (z.y + z.h / 2.0)
+        } else {
+            punto[0] - (z.x + z.w / 2.0)
+        };
+        z.contiene(punto) && al_eje.abs() <= self.media_via
+    }
+
+    // Si no hay vehículos sobre el paso ni a punto de llegar a él, a lo largo de la vía
+    pub fn despejado<'a>(&self, mut posiciones: impl Iterator<Item = &'a [f32; 2]>) -> bool {
+        let z = &self.zona;
+        let alrededor = if self.acceso.eje_horizontal() {
+            Rect::new(z.x - MARGEN_PASO, z.y, z.w + 2.0 * MARGEN_PASO, z.h)
+        } else {
+            Rect::new(z.x, z.y - MARGEN_PASO, z.w, z.h + 2.0 * MARGEN_PASO)
+        };
+        !posiciones.any(|&p| alrededor.contiene(p))
+    }
+
+    // Los peatones pueden pasar si la fase no da verde a nadie que vaya recto por el paso
+    pub fn compatible(&self, fase: &[Direccion]) -> bool {
+        !fase.iter().any(|&d| self.cruza(d))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum EstadoPeatonal {
+    NoPasar,
+    Pasar,
+    Despejar, // Parpadea: los que ya cruzan terminan, nadie más empieza
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Peaton {
+    pub posicion: [f32; 2],
+    pub paso: usize,          // Índice en EstadoCompartido::pasos
+    pub destino: [f32; 2],    // Extremo del paso en la acera de enfrente
+    pub cruzando: bool,
+    pub color: Color,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Semaforo {
     pub posicion: [f32; 2],
     pub estado: EstadoSemaforo,
-    pub direccion: String,
+    pub direccion: Direccion,
+    pub nodo: usize,   // Cruce de la red
+    pub enlace: usize, // Enlace que llega al cruce y que controla
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum EstadoSemaforo {
     Rojo,
     Amarillo,
     Verde,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+impl EstadoSemaforo {
+    pub fn nombre(self) -> &'static str {
+        match self {
+            EstadoSemaforo::Rojo => "rojo",
+            EstadoSemaforo::Amarillo => "amarillo",
+            EstadoSemaforo::Verde => "verde",
+        }
+    }
+}
+
+// Choque entre dos vehículos
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Choque {
+    pub posicion: [f32; 2], // Punto de contacto
+    pub tiempo: f32,        // s simulados
+    pub tipos: [TipoVehiculo; 2],
+    pub enlaces: [usize; 2], // Enlace por el que iba cada uno
+    pub gravedad: Gravedad,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub enum TipoVehiculo {
     Automovil,
     Camioneta,
     Camion,
+    Ambulancia,
+    Policia,
+}
+
+impl TipoVehiculo {
+    // Largo y ancho del vehículo
+    pub fn dimensiones(&self) -> [f32; 2] {
+        match self {
+            TipoVehiculo::Automovil => [30.0, 15.0],
+            TipoVehiculo::Camioneta => [35.0, 18.0],
+            TipoVehiculo::Camion => [45.0, 20.0],
+            TipoVehiculo::Ambulancia => [40.0, 20.0],
+            TipoVehiculo::Policia => [32.0, 17.0],
+        }
+    }
+
+    pub fn nombre(&self) -> &'static str {
+        match self {
+            TipoVehiculo::Automovil => "automóvil",
+            TipoVehiculo::Camioneta => "camioneta",
+            TipoVehiculo::Camion => "camión",
+            TipoVehiculo::Ambulancia => "ambulancia",
+            TipoVehiculo::Policia => "policía",
+        }
+    }
+
+    pub fn es_emergencia(&self) -> bool {
+        matches!(self, TipoVehiculo::Ambulancia | TipoVehiculo::Policia)
+    }
+
+    // Aceleración máxima en px/s²
+    pub fn aceleracion_maxima(&self) -> f32 {
+        match self {
+            TipoVehiculo::Automovil => 12.0,
+            TipoVehiculo::Camioneta => 9.0,
+            TipoVehiculo::Camion => 5.0,
+            TipoVehiculo::Ambulancia => 14.0,
+            TipoVehiculo::Policia => 16.0,
+        }
+    }
+
+    // Frenado que el conductor considera cómodo, en px/s²
+    pub fn frenado_comodo(&self) -> f32 {
+        match self {
+            TipoVehiculo::Automovil => 20.0,
+            TipoVehiculo::Camioneta => 16.0,
+            TipoVehiculo::Camion => 12.0,
+            TipoVehiculo::Ambulancia | TipoVehiculo::Policia => 22.0,
+        }
+    }
+
+    // Fracción de la velocidad del escenario a la que quiere ir
+    pub fn factor_velocidad(&self) -> f32 {
+        match self {
+            TipoVehiculo::Automovil => 1.0,
+            TipoVehiculo::Camioneta => 0.9,
+            TipoVehiculo::Camion => 0.75,
+            TipoVehiculo::Ambulancia | TipoVehiculo::Policia => 1.3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Movimiento {
+    Recto,
+    Izquierda,
+    Derecha,
+}
+
+// Curva que sigue un vehículo al girar dentro de la intersección: un cuarto de
+// círculo desde la entrada de su carril hasta el carril de salida. Si el carril de
+// salida queda a otra distancia del eje que el de entrada (la vía de salida tiene
+// menos carriles), el cuarto de círculo se estira en un cuarto de elipse.
+pub struct Curva {
+    pub centro: [f32; 2],
+    pub radio: f32,    // Promedio de los dos semiejes
+    escala: [f32; 2],  // Semieje hacia la entrada y semieje de avance, divididos por el radio
+    inicio: [f32; 2],  // Del centro de la curva al punto de entrada, normalizado
+    avance: [f32; 2],  // Dirección de llegada
+}
+
+impl Curva {
+    // Curva dentro del cruce con centro en `cruce` y media caja `media_caja`, desde
+    // un carril a `entrada` px del eje de la vía hasta uno a `salida` px del eje
+    pub fn new(cruce: [f32; 2], media_caja: f32, [entrada, salida]: [f32; 2], direccion: Direccion, movimiento: Movimiento) -> Option<Self> {
+        let [hx, hy] = direccion.vector();
+        let derecha = [-hy, hx]; // La derecha del conductor
+        let punto_entrada = [
+            cruce[0] - hx * media_caja + derecha[0] * entrada,
+            cruce[1] - hy * media_caja + derecha[1] * entrada,
+        ];
+
+        // Derecha: giro cerrado hacia la esquina cercana. Izquierda: giro amplio
+        // que cruza hasta el carril del otro lado.
+        let (lateral, adelante, lado) = match movimiento {
+            Movimiento::Recto => return None,
+            Movimiento::Derecha => (media_caja - entrada, media_caja - salida, 1.0),
+            Movimiento::Izquierda => (media_caja + entrada, media_caja + salida, -1.0),
+        };
+        let radio = (lateral + adelante) / 2.0;
+        let centro = [punto_entrada[0] + derecha[0] * lateral * lado, punto_entrada[1] + derecha[1] * lateral * lado];
+
+        Some(Self {
+            centro,
+            radio,
+            escala: [lateral / radio, adelante / radio],
+            inicio: [-derecha[0] * lado, -derecha[1] * lado],
+            avance: [hx, hy],
+        })
+    }
+
+    pub fn largo(&self) -> f32 {
+        self.radio * std::f32::consts::FRAC_PI_2
+    }
+
+    // Posición y rumbo después de recorrer cierta distancia de la curva
+    pub fn punto(&self, recorrido: f32) -> ([f32; 2], f32) {
+        let fi = (recorrido / self.radio).min(std::f32::consts::FRAC_PI_2);
+        let (seno, coseno) = fi.sin_cos();
+        let [lateral, adelante] = self.escala;
+        let posicion = [
+            self.centro[0] + self.radio * (self.inicio[0] * coseno * lateral + self.avance[0] * seno * adelante),
+            self.centro[1] + self.radio * (self.inicio[1] * coseno * lateral + self.avance[1] * seno * adelante),
+        ];
+        let rumbo = [
+            self.avance[0] * coseno * adelante - self.inicio[0] * seno * lateral,
+            self.avance[1] * coseno * adelante - self.inicio[1] * seno * lateral,
+        ];
+        (posicion, rumbo[1].atan2(rumbo[0]))
+    }
 }
\ No newline at end of file
@@ -0,0 +1,437 @@
+// red.rs
+// Red de calles como grafo: los nodos son cruces o extremos del mapa y los
+// enlaces son tramos de vía en un solo sentido entre dos nodos. Todas las vías
+// son horizontales o verticales, así que cada enlace va en una de las cuatro
+// direcciones y los vehículos se siguen moviendo con su avance a lo largo de ella.
+// Los carriles de un enlace se numeran desde la línea central (0) hacia la acera.
+use crate::escenario::{Escenario, PlanoRed};
+use crate::modelo::*;
+
+pub struct Nodo {
+    pub posicion: [f32; 2],
+    pub semaforo: bool,        // Cruce con semáforos
+    pub media_caja: f32,       // Mitad del lado de la intersección: la de la vía más ancha
+    pub entrantes: Vec<usize>, // Enlaces que llegan al nodo, en el orden de la red
+    pub salientes: Vec<usize>, // Enlaces que salen del nodo
+}
+
+pub struct Enlace {
+    pub desde: usize,
+    pub hasta: usize,
+    pub direccion: Direccion,
+    pub largo: f32,      // px entre los centros de los dos nodos
+    pub carriles: usize,
+    pub lineas: Vec<usize>, // Línea recta de cada carril; la comparten los enlaces seguidos en la misma dirección
+}
+
+pub struct Red {
+    pub nodos: Vec<Nodo>,
+    pub enlaces: Vec<Enlace>,
+    pub entradas: Vec<usize>, // Enlaces que salen de un extremo: por ahí aparecen los vehículos
+    pub num_lineas: usize,
+    // Distancia desde el final de cada enlace hasta cada nodo, sin vueltas en U
+    restante: Vec<Vec<f32>>,
+}
+
+impl Red {
+    // Arma el grafo a partir del plano del escenario, que ya fue validado. Cada
+    // vía da dos enlaces seguidos, uno por sentido.
+    pub fn new(plano: &PlanoRed) -> Self {
+        let mut nodos: Vec<Nodo> = plano.nodos.iter()
+            .map(|nodo| Nodo { posicion: nodo.posicion, semaforo: false, media_caja: 0.0, entrantes: Vec::new(), salientes: Vec::new() })
+            .collect();
+
+        let mut enlaces = Vec::new();
+        for via in &plano.vias {
+            let [a, b] = via.nodos;
+            for (desde, hasta) in [(a, b), (b, a)] {
+                let inicio = nodos[desde].posicion;
+                let fin = nodos[hasta].posicion;
+                // validar() garantiza que la vía es horizontal o vertical
+                let direccion = direccion_entre(inicio, fin).unwrap();
+                nodos[desde].salientes.push(enlaces.len());
+                nodos[hasta].entrantes.push(enlaces.len());
+                enlaces.push(Enlace {
+                    desde,
+                    hasta,
+                    direccion,
+                    largo: direccion.avance(fin) - direccion.avance(inicio),
+                    carriles: via.carriles,
+                    lineas: Vec::new(),
+                });
+            }
+            let media_via = via.carriles as f32 * ANCHO_CARRIL;
+            for nodo in [a, b] {
+                nodos[nodo].media_caja = nodos[nodo].media_caja.max(media_via);
+            }
+        }
+
+        // Por defecto llevan semáforo los cruces de tres o más vías; los extremos nunca
+        for (nodo, plano) in nodos.iter_mut().zip(&plano.nodos) {
+            let vias = nodo.salientes.len();
+            nodo.semaforo = vias > 1 && plano.semaforo.unwrap_or(vias >= 3);
+        }
+
+        // Numerar las líneas rectas de cada carril ordenadas por dirección, para que
+        // los vehículos se procesen en el mismo orden que cuando había un solo cruce
+        let lateral = |enlace: &Enlace| {
+            let posicion = nodos[enlace.desde].posicion;
+            if enlace.direccion.eje_horizontal() { posicion[1] } else { posicion[0] }
+        };
+        let mut lineas: Vec<(Direccion, f32, usize)> = enlaces.iter()
+            .flat_map(|e| (0..e.carriles).map(move |carril| (e.direccion, lateral(e), carril)))
+            .collect();
+        lineas.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)).then(a.2.cmp(&b.2)));
+        lineas.dedup();
+        for enlace in enlaces.iter_mut() {
+            enlace.lineas = (0..enlace.carriles)
+                .map(|carril| {
+                    let linea = (enlace.direccion, lateral(enlace), carril);
+                    lineas.iter().position(|&l| l == linea).unwrap()
+                })
+                .collect();
+        }
+
+        let entradas = (0..enlaces.len())
+            .filter(|&i| nodos[enlaces[i].desde].salientes.len() == 1)
+            .collect();
+
+        let mut red = Self { nodos, enlaces, entradas, num_lineas: lineas.len(), restante: Vec::new() };
+        red.restante = red.calcular_restante();
+        red
+    }
+
+    // Relaja las distancias hasta que no cambian. Las redes son chicas, así que
+    // basta con repetir sobre todos los enlaces.
+    fn calcular_restante(&self) -> Vec<Vec<f32>> {
+        let mut restante = vec![vec![f32::INFINITY; self.nodos.len()]; self.enlaces.len()];
+        for (i, enlace) in self.enlaces.iter().enumerate() {
+            restante[i][enlace.hasta] = 0.0;
+        }
+
+        let mut cambio = true;
+        while cambio {
+            cambio = false;
+            for i in 0..self.enlaces.len() {
+                for siguiente in self.continuaciones(i) {
+                    let desde_siguiente = restante[siguiente].clone();
+                    for (actual, resto) in restante[i].iter_mut().zip(desde_siguiente) {
+                        let por_ahi = self.enlaces[siguiente].largo + resto;
+                        if por_ahi < *actual {
+                            *actual = por_ahi;
+                            cambio = true;
+                        }
+                    }
+                }
+            }
+        }
+        restante
+    }
+
+    // Enlaces por los que se puede seguir al final de `enlace`, sin volver por donde se vino
+    fn continuaciones(&self, enlace: usize) -> impl Iterator<Item = usize> + '_ {
+        let actual = &self.enlaces[enlace];
+        self.nodos[actual.hasta].salientes.iter()
+            .copied()
+            .filter(move |&otro| self.enlaces[otro].direccion != actual.direccion.opuesta())
+    }
+
+    pub fn es_extremo(&self, nodo: usize) -> bool {
+        self.nodos[nodo].salientes.len() == 1
+    }
+
+    pub fn semaforizados(&self) -> Vec<usize> {
+        (0..self.nodos.len()).filter(|&i| self.nodos[i].semaforo).collect()
+    }
+
+    // Enlace por el que se sale de `enlace` haciendo `movimiento`, si existe
+    pub fn enlace_tras(&self, enlace: usize, movimiento: Movimiento) -> Option<usize> {
+        let direccion = self.enlaces[enlace].direccion.salida(movimiento);
+        self.continuaciones(enlace).find(|&otro| self.enlaces[otro].direccion == direccion)
+    }
+
+    // Siguiente enlace del camino más corto hacia `destino`, o None si el enlace
+    // ya termina ahí o no hay camino
+    pub fn siguiente_enlace(&self, enlace: usize, destino: usize) -> Option<usize> {
+        if self.enlaces[enlace].hasta == destino {
+            return None;
+        }
+        self.continuaciones(enlace)
+            .map(|otro| (otro, self.enlaces[otro].largo + self.restante[otro][destino]))
+            .filter(|(_, distancia)| distancia.is_finite())
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(otro, _)| otro)
+    }
+
+    // Enlaces del camino más corto desde `enlace` (incluido) hasta el nodo
+    // `destino`, o None si desde ahí no se puede llegar
+    pub fn ruta(&self, enlace: usize, destino: usize) -> Option<Vec<usize>> {
+        if !self.restante[enlace][destino].is_finite() {
+            return None;
+        }
+        let mut ruta = vec![enlace];
+        while let Some(siguiente) = self.siguiente_enlace(ruta[ruta.len() - 1], destino) {
+            ruta.push(siguiente);
+        }
+        Some(ruta)
+    }
+
+    // Lo que hace el vehículo al final de `enlace` para seguir hacia `destino`
+    pub fn maniobra(&self, enlace: usize, destino: usize) -> Movimiento {
+        let Some(siguiente) = self.siguiente_enlace(enlace, destino) else {
+            return Movimiento::Recto;
+        };
+        let desde = self.enlaces[enlace].direccion;
+        let hacia = self.enlaces[siguiente].direccion;
+        [Movimiento::Recto, Movimiento::Izquierda, Movimiento::Derecha]
+            .into_iter()
+            .find(|&m| desde.salida(m) == hacia)
+            .unwrap_or(Movimiento::Recto)
+    }
+
+    // Entrada y extremo de salida del camino más corto entre extremos que pasa por
+    // `enlace` lo antes posible, para mandar un vehículo hasta él
+    pub fn ruta_por(&self, enlace: usize) -> Option<(usize, usize)> {
+        let mut mejor: Option<(f32, usize, usize)> = None;
+        for &entrada in &self.entradas {
+            for destino in (0..self.nodos.len()).filter(|&n| self.es_extremo(n)) {
+                let Some(ruta) = self.ruta(entrada, destino) else { continue };
+                let Some(posicion) = ruta.iter().position(|&e| e == enlace) else { continue };
+                let recorrido: f32 = ruta[..posicion].iter().map(|&e| self.enlaces[e].largo).sum();
+                if mejor.is_none_or(|(distancia, _, _)| recorrido < distancia) {
+                    mejor = Some((recorrido, entrada, destino));
+                }
+            }
+        }
+        mejor.map(|(_, entrada, destino)| (entrada, destino))
+    }
+
+    // Extremo más cercano al que se puede llegar desde el final de `enlace`
+    pub fn extremo_cercano(&self, enlace: usize) -> Option<usize> {
+        (0..self.nodos.len())
+            .filter(|&nodo| self.es_extremo(nodo) && self.restante[enlace][nodo].is_finite())
+            .min_by(|&a, &b| self.restante[enlace][a].total_cmp(&self.restante[enlace][b]))
+    }
+
+    // Centro del nodo al que llega el enlace
+    pub fn fin(&self, enlace: usize) -> [f32; 2] {
+        self.nodos[self.enlaces[enlace].hasta].posicion
+    }
+
+    // Mitad del ancho de la vía del enlace
+    pub fn media_via(&self, enlace: usize) -> f32 {
+        self.enlaces[enlace].carriles as f32 * ANCHO_CARRIL
+    }
+
+    // Carril en el que conviene llegar al cruce para hacer el movimiento, si importa
+    pub fn carril_para(&self, enlace: usize, movimiento: Movimiento) -> Option<usize> {
+        match movimiento {
+            Movimiento::Recto => None,
+            Movimiento::Izquierda => Some(0),
+            Movimiento::Derecha => Some(self.enlaces[enlace].carriles - 1),
+        }
+    }
+
+    // Línea recta por la que va el vehículo
+    pub fn linea_de(&self, carro: &Carro) -> usize {
+        self.enlaces[carro.enlace].lineas[carro.carril]
+    }
+
+    // Punto donde empieza el carril del enlace, a la derecha del eje de la vía
+    pub fn inicio_carril(&self, enlace: usize, carril: usize) -> [f32; 2] {
+        let e = &self.enlaces[enlace];
+        let [x, y] = self.nodos[e.desde].posicion;
+        let [dx, dy] = e.direccion.vector();
+        let desplazamiento = desplazamiento_carril(carril);
+        [x - dy * desplazamiento, y + dx * desplazamiento]
+    }
+
+    // Coordenada lateral (y en vías horizontales, x en verticales) del centro del carril
+    pub fn lateral_carril(&self, enlace: usize, carril: usize) -> f32 {
+        let [x, y] = self.inicio_carril(enlace, carril);
+        if self.enlaces[enlace].direccion.eje_horizontal() { y } else { x }
+    }
+
+    // Avance de la línea de parada al final del enlace. Las posiciones del escenario
+    // son para vías de un carril; en cruces más anchos la línea se corre hacia atrás.
+    pub fn linea_parada(&self, enlace: usize, escenario: &Escenario) -> f32 {
+        let direccion = self.enlaces[enlace].direccion;
+        let ensanche = self.nodos[self.enlaces[enlace].hasta].media_caja - MEDIA_CAJA;
+        direccion.avance(self.fin(enlace)) - escenario.retiro_linea_parada(direccion) - ensanche
+    }
+
+    // Avance donde el enlace entra a la caja del cruce en que termina
+    pub fn entrada_caja(&self, enlace: usize) -> f32 {
+        let e = &self.enlaces[enlace];
+        e.direccion.avance(self.fin(enlace)) - self.nodos[e.hasta].media_caja
+    }
+
+    // Corre hacia atrás y hacia la acera una posición pensada para un cruce de vías
+    // de un carril (semáforos), según el ancho del cruce y de la vía del enlace
+    pub fn ajustar_al_ancho(&self, enlace: usize, punto: [f32; 2]) -> [f32; 2] {
+        let e = &self.enlaces[enlace];
+        let [dx, dy] = e.direccion.vector();
+        let atras = self.nodos[e.hasta].media_caja - MEDIA_CAJA;
+        let afuera = self.media_via(enlace) - MEDIA_CAJA;
+        [punto[0] - dx * atras - dy * afuera, punto[1] - dy * atras + dx * afuera]
+    }
+
+    // Cruce en cuya caja está el punto
+    pub fn cruce_en(&self, punto: [f32; 2]) -> Option<usize> {
+        (0..self.nodos.len()).find(|&i| {
+            let [x, y] = self.nodos[i].posicion;
+            let media = self.nodos[i].media_caja;
+            !self.es_extremo(i) && (punto[0] - x).abs() <= media && (punto[1] - y).abs() <= media
+        })
+    }
+
+    // Un enlace por vía (cada vía creó dos enlaces seguidos)
+    pub fn vias(&self) -> impl Iterator<Item = &Enlace> {
+        self.enlaces.iter().step_by(2)
+    }
+
+    // Rectángulo de asfalto de la vía del enlace. En los cruces se alarga media
+    // caja para que la intersección quede cubierta aunque no siga recto.
+    pub fn zona_via(&self, enlace: &Enlace) -> Rect {
+        let extension = |nodo: usize| if self.es_extremo(nodo) { 0.0 } else { self.nodos[nodo].media_caja };
+        let media = enlace.carriles as f32 * ANCHO_CARRIL;
+        let eje = if enlace.direccion.eje_horizontal() { 0 } else { 1 };
+        // Nodos de la vía de izquierda a derecha o de arriba hacia abajo
+        let (mut a, mut b) = (enlace.desde, enlace.hasta);
+        if self.nodos[a].posicion[eje] > self.nodos[b].posicion[eje] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        let inicio = self.nodos[a].posicion[eje] - extension(a);
+        let fin = self.nodos[b].posicion[eje] + extension(b);
+        let centro = self.nodos[a].posicion[1 - eje];
+
+        if eje == 0 {
+            Rect::new(inicio, centro - media, fin - inicio, 2.0 * media)
+        } else {
+            Rect::new(centro - media, inicio, 2.0 * media, fin - inicio)
+        }
+    }
+}
+
+// Del eje de la vía al centro del carril
+pub fn desplazamiento_carril(carril: usize) -> f32 {
+    DESPLAZAMIENTO_CARRIL + carril as f32 * ANCHO_CARRIL
+}
+
+// Dirección para ir en línea recta de un punto a otro, si la recta es horizontal o vertical
+pub fn direccion_entre(desde: [f32; 2], hasta: [f32; 2]) -> Option<Direccion> {
+    let dx = hasta[0] - desde[0];
+    let dy = hasta[1] - desde[1];
+    match (dx == 0.0, dy == 0.0) {
+        (false, true) => Some(if dx > 0.0 { Direccion::Este } else { Direccion::Oeste }),
+        (true, false) => Some(if dy > 0.0 { Direccion::Sur } else { Direccion::Norte }),
+        _ => None, // Diagonal o de largo cero
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::escenario::{NodoPlano, ViaPlano};
+
+    fn armar(posiciones: &[[f32; 2]], vias: &[[usize; 2]]) -> Red {
+        Red::new(&PlanoRed {
+            nodos: posiciones.iter().map(|&posicion| NodoPlano { posicion, semaforo: None, desfase: None }).collect(),
+            vias: vias.iter().map(|&nodos| ViaPlano { nodos, carriles: 1 }).collect(),
+        })
+    }
+
+    // La de escenario_cuadricula.toml: cruces 0 a 3 a 250 px uno de otro y un
+    // extremo por cada calle
+    fn cuadricula() -> Red {
+        armar(
+            &[
+                [225.0, 225.0], [475.0, 225.0], [225.0, 475.0], [475.0, 475.0],
+                [0.0, 225.0], [225.0, 0.0], [475.0, 0.0], [700.0, 225.0],
+                [0.0, 475.0], [225.0, 700.0], [475.0, 700.0], [700.0, 475.0],
+            ],
+            &[[0, 1], [2, 3], [0, 2], [1, 3], [4, 0], [5, 0], [6, 1], [7, 1], [8, 2], [9, 2], [10, 3], [11, 3]],
+        )
+    }
+
+    fn enlace(red: &Red, desde: usize, hasta: usize) -> usize {
+        red.enlaces.iter().position(|e| e.desde == desde && e.hasta == hasta).unwrap()
+    }
+
+    #[test]
+    fn la_cuadricula_tiene_cuatro_cruces_con_semaforo() {
+        let red = cuadricula();
+        assert_eq!(red.enlaces.len(), 24);
+        assert_eq!(red.semaforizados(), vec![0, 1, 2, 3]);
+        assert_eq!(red.entradas.len(), 8);
+        assert!((4..12).all(|nodo| red.es_extremo(nodo)));
+    }
+
+    #[test]
+    fn la_ruta_sigue_el_camino_mas_corto() {
+        let red = cuadricula();
+        // Del extremo oeste de arriba al extremo norte de la derecha: este y luego norte
+        let esperada = vec![enlace(&red, 4, 0), enlace(&red, 0, 1), enlace(&red, 1, 6)];
+        assert_eq!(red.ruta(enlace(&red, 4, 0), 6), Some(esperada));
+        assert_eq!(red.maniobra(enlace(&red, 4, 0), 6), Movimiento::Recto);
+        assert_eq!(red.maniobra(enlace(&red, 0, 1), 6), Movimiento::Izquierda);
+        // El enlace que ya termina en el destino es toda la ruta
+        assert_eq!(red.ruta(enlace(&red, 1, 7), 7), Some(vec![enlace(&red, 1, 7)]));
+    }
+
+    #[test]
+    fn un_destino_inalcanzable_no_tiene_ruta() {
+        // Sin vueltas en U no se sale de un extremo
+        let red = cuadricula();
+        let al_extremo = enlace(&red, 0, 4);
+        assert_eq!(red.ruta(al_extremo, 7), None);
+        assert_eq!(red.siguiente_enlace(al_extremo, 7), None);
+        assert_eq!(red.maniobra(al_extremo, 7), Movimiento::Recto);
+
+        // Dos vías que no se tocan
+        let red = armar(&[[0.0, 100.0], [200.0, 100.0], [0.0, 300.0], [200.0, 300.0]], &[[0, 1], [2, 3]]);
+        assert_eq!(red.ruta(enlace(&red, 0, 1), 3), None);
+        assert_eq!(red.ruta_por(enlace(&red, 2, 3)), Some((enlace(&red, 2, 3), 3)));
+    }
+
+    #[test]
+    fn cruce_en_usa_la_caja_de_cada_cruce() {
+        let red = cuadricula();
+        for (i, nodo) in red.nodos.iter().enumerate() {
+            let [x, y] = nodo.posicion;
+            let media = nodo.media_caja;
+            let esperado = if red.es_extremo(i) { None } else { Some(i) };
+            assert_eq!(red.cruce_en([x, y]), esperado);
+            assert_eq!(red.cruce_en([x + media, y - media]), esperado);
+            assert_eq!(red.cruce_en([x - media - 1.0, y]), None);
+        }
+        // En la mitad de una vía no hay cruce
+        assert_eq!(red.cruce_en([350.0, 225.0]), None);
+    }
+
+    #[test]
+    fn zona_via_cubre_la_via_y_las_cajas_de_sus_cruces() {
+        let red = cuadricula();
+        for via in red.vias() {
+            let zona = red.zona_via(via);
+            for nodo in [via.desde, via.hasta] {
+                let [x, y] = red.nodos[nodo].posicion;
+                let media = red.nodos[nodo].media_caja;
+                assert!(zona.contiene([x, y]));
+                if !red.es_extremo(nodo) {
+                    // Toda la caja del cruce queda cubierta
+                    for esquina in [[x - media, y - media], [x + media, y + media]] {
+                        assert!(zona.contiene(esquina), "{:?} fuera de {:?}", esquina, (zona.x, zona.y, zona.w, zona.h));
+                        assert_eq!(red.cruce_en(esquina), Some(nodo));
+                    }
+                }
+            }
+        }
+
+        // La vía de arriba entre los cruces 0 y 1 y la que sale al extremo oeste
+        let zona = red.zona_via(&red.enlaces[enlace(&red, 0, 1)]);
+        assert_eq!((zona.x, zona.y, zona.w, zona.h), (200.0, 200.0, 300.0, 50.0));
+        let zona = red.zona_via(&red.enlaces[enlace(&red, 4, 0)]);
+        assert_eq!((zona.x, zona.y, zona.w, zona.h), (0.0, 200.0, 250.0, 50.0));
+    }
+}
@@ -0,0 +1,276 @@
+// registro.rs
+// Registro de la corrida para analizarla con otras herramientas: la posición y
+// velocidad de cada vehículo en cada tick, más los eventos (aparición, cambio de
+// semáforo, parada, choque y salida). Todo va a un solo archivo, en CSV o en JSON
+// de una línea por registro, con las mismas columnas en ambos.
+use crate::modelo::{Carro, EstadoSemaforo, Semaforo};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const COLUMNAS: &str = "tiempo,evento,id,tipo,loco,direccion,enlace,carril,x,y,velocidad,semaforo";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Formato {
+    Csv,
+    Json, // Un objeto JSON por línea
+}
+
+impl Formato {
+    // Según la extensión del archivo
+    pub fn de_ruta(ruta: &Path) -> Option<Self> {
+        match ruta.extension()?.to_str()? {
+            "csv" => Some(Formato::Csv),
+            "json" | "jsonl" | "ndjson" => Some(Formato::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Evento {
+    Posicion, // Muestra de cada tick
+    Aparicion,
+    Semaforo,
+    Parada,
+    Choque,
+    Salida,
+}
+
+impl Evento {
+    pub fn nombre(self) -> &'static str {
+        match self {
+            Evento::Posicion => "posicion",
+            Evento::Aparicion => "aparicion",
+            Evento::Semaforo => "semaforo",
+            Evento::Parada => "parada",
+            Evento::Choque => "choque",
+            Evento::Salida => "salida",
+        }
+    }
+}
+
+pub struct Registro<W: Write = BufWriter<File>> {
+    escritor: W,
+    formato: Formato,
+    error: Option<io::Error>, // El primero al escribir; se informa al terminar el tick
+    eventos: bool,            // Hubo eventos desde la última vez que se vació
+}
+
+impl Registro {
+    pub fn crear(ruta: &Path) -> Result<Self, String> {
+        let formato = Formato::de_ruta(ruta)
+            .ok_or("el registro debe terminar en .csv, .json, .jsonl o .ndjson")?;
+        let archivo = File::create(ruta).map_err(|e| e.to_string())?;
+        Registro::new(BufWriter::new(archivo), formato).map_err(|e| e.to_string())
+    }
+}
+
+impl<W: Write> Registro<W> {
+    pub fn new(escritor: W, formato: Formato) -> io::Result<Self> {
+        let mut registro = Self { escritor, formato, error: None, eventos: false };
+        if formato == Formato::Csv {
+            writeln!(registro.escritor, "{}", COLUMNAS)?;
+        }
+        Ok(registro)
+    }
+
+    // Una fila de un vehículo, con el estado del semáforo al final de su enlace
+    pub fn vehiculo(&mut self, tiempo: f32, evento: Evento, carro: &Carro, senal: Option<EstadoSemaforo>) {
+        self.fila(tiempo, evento, [
+            Some(carro.id.to_string()),
+            Some(texto(carro.tipo.nombre())),
+            Some(carro.loco.to_string()),
+            Some(texto(carro.direccion.nombre())),
+            Some(carro.enlace.to_string()),
+            Some(carro.carril.to_string()),
+            Some(format!("{:.2}", carro.posicion[0])),
+            Some(format!("{:.2}", carro.posicion[1])),
+            Some(format!("{:.2}", carro.velocidad)),
+            senal.map(|s| texto(s.nombre())),
+        ])
+    }
+
+    // Cambio de un semáforo; las columnas del vehículo quedan vacías
+    pub fn semaforo(&mut self, tiempo: f32, semaforo: &Semaforo) {
+        self.fila(tiempo, Evento::Semaforo, [
+            None,
+            None,
+            None,
+            Some(texto(semaforo.direccion.nombre())),
+            Some(semaforo.enlace.to_string()),
+            None,
+            Some(format!("{:.2}", semaforo.posicion[0])),
+            Some(format!("{:.2}", semaforo.posicion[1])),
+            None,
+            Some(texto(semaforo.estado.nombre())),
+        ])
+    }
+
+    // Al final de cada tick. Solo se vacía si hubo eventos: las posiciones de
+    // cada tick se acumulan en el búfer hasta el siguiente evento o hasta terminar.
+    pub fn terminar_tick(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if std::mem::take(&mut self.eventos) {
+            self.escritor.flush()?;
+        }
+        Ok(())
+    }
+
+    // Al terminar la simulación, para que quede todo en el archivo
+    pub fn terminar(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.escritor.flush(),
+        }
+    }
+
+    // Los textos ya vienen entre comillas; los vacíos son null en JSON
+    fn fila(&mut self, tiempo: f32, evento: Evento, valores: [Option<String>; 10]) {
+        if self.error.is_some() {
+            return;
+        }
+        let es_evento = evento != Evento::Posicion;
+        let tiempo = format!("{:.3}", tiempo);
+        let evento = texto(evento.nombre());
+        let escrito = match self.formato {
+            Formato::Csv => {
+                let campos: Vec<String> = valores.into_iter().map(Option::unwrap_or_default).collect();
+                writeln!(self.escritor, "{},{},{}", tiempo, evento, campos.join(","))
+            }
+            Formato::Json => {
+                let campos: Vec<String> = COLUMNAS.split(',').skip(2).zip(valores)
+                    .map(|(nombre, valor)| format!("\"{}\":{}", nombre, valor.unwrap_or_else(|| "null".to_string())))
+                    .collect();
+                writeln!(self.escritor, "{{\"tiempo\":{},\"evento\":{},{}}}", tiempo, evento, campos.join(","))
+            }
+        };
+        self.error = escrito.err();
+        self.eventos |= es_evento;
+    }
+}
+
+// Nombres fijos sin comillas ni comas, así sirven igual en CSV y en JSON
+fn texto(nombre: &str) -> String {
+    format!("\"{}\"", nombre)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modelo::*;
+
+    fn carro() -> Carro {
+        Carro {
+            id: 7,
+            aparicion: 1.0,
+            salida: None,
+            origen: 2,
+            posicion: [100.0, 337.5],
+            angulo: 0.0,
+            direccion: Direccion::Este,
+            enlace: 2,
+            carril: 0,
+            destino: 3,
+            movimiento: Movimiento::Recto,
+            recorrido_giro: None,
+            color: Color::WHITE,
+            velocidad: 12.5,
+            velocidad_deseada: 40.0,
+            tipo: TipoVehiculo::Camion,
+            loco: true,
+            orillado: false,
+            siniestro: None,
+            demora: 0.0,
+            paradas: 0,
+            detenido: false,
+        }
+    }
+
+    fn semaforo() -> Semaforo {
+        Semaforo { posicion: [365.0, 370.0], estado: EstadoSemaforo::Verde, direccion: Direccion::Norte, nodo: 0, enlace: 5 }
+    }
+
+    // Una parada en rojo y un cambio de semáforo, escritos en memoria
+    fn escribir(formato: Formato) -> String {
+        let mut registro = Registro::new(Vec::new(), formato).unwrap();
+        registro.vehiculo(1.5, Evento::Parada, &carro(), Some(EstadoSemaforo::Rojo));
+        registro.semaforo(2.0, &semaforo());
+        registro.terminar().unwrap();
+        String::from_utf8(registro.escritor).unwrap()
+    }
+
+    #[test]
+    fn el_formato_sale_de_la_extension() {
+        assert_eq!(Formato::de_ruta(Path::new("corrida.csv")), Some(Formato::Csv));
+        for ruta in ["corrida.json", "corrida.jsonl", "corrida.ndjson"] {
+            assert_eq!(Formato::de_ruta(Path::new(ruta)), Some(Formato::Json), "{}", ruta);
+        }
+        assert_eq!(Formato::de_ruta(Path::new("corrida.txt")), None);
+        assert_eq!(Formato::de_ruta(Path::new("corrida")), None);
+        // Se rechaza antes de crear el archivo
+        assert_eq!(
+            Registro::crear(Path::new("corrida.txt")).err(),
+            Some("el registro debe terminar en .csv, .json, .jsonl o .ndjson".to_string())
+        );
+    }
+
+    #[test]
+    fn csv_con_cabecera_y_columnas_vacias() {
+        assert_eq!(
+            escribir(Formato::Csv),
+            "tiempo,evento,id,tipo,loco,direccion,enlace,carril,x,y,velocidad,semaforo\n\
+             1.500,\"parada\",7,\"camión\",true,\"este\",2,0,100.00,337.50,12.50,\"rojo\"\n\
+             2.000,\"semaforo\",,,,\"norte\",5,,365.00,370.00,,\"verde\"\n"
+        );
+    }
+
+    #[test]
+    fn json_de_un_objeto_por_linea() {
+        assert_eq!(
+            escribir(Formato::Json),
+            "{\"tiempo\":1.500,\"evento\":\"parada\",\"id\":7,\"tipo\":\"camión\",\"loco\":true,\"direccion\":\"este\",\
+             \"enlace\":2,\"carril\":0,\"x\":100.00,\"y\":337.50,\"velocidad\":12.50,\"semaforo\":\"rojo\"}\n\
+             {\"tiempo\":2.000,\"evento\":\"semaforo\",\"id\":null,\"tipo\":null,\"loco\":null,\"direccion\":\"norte\",\
+             \"enlace\":5,\"carril\":null,\"x\":365.00,\"y\":370.00,\"velocidad\":null,\"semaforo\":\"verde\"}\n"
+        );
+    }
+
+    // Cuenta las veces que se vacía
+    #[derive(Default)]
+    struct Contador {
+        vaciados: usize,
+    }
+
+    impl Write for Contador {
+        fn write(&mut self, datos: &[u8]) -> io::Result<usize> {
+            Ok(datos.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.vaciados += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn solo_se_vacia_con_eventos_y_al_terminar() {
+        let mut registro = Registro::new(Contador::default(), Formato::Json).unwrap();
+        for tick in 0..10 {
+            registro.vehiculo(tick as f32, Evento::Posicion, &carro(), None);
+            registro.terminar_tick().unwrap();
+        }
+        assert_eq!(registro.escritor.vaciados, 0);
+
+        registro.vehiculo(10.0, Evento::Salida, &carro(), None);
+        registro.terminar_tick().unwrap();
+        registro.terminar_tick().unwrap();
+        assert_eq!(registro.escritor.vaciados, 1);
+
+        registro.vehiculo(11.0, Evento::Posicion, &carro(), None);
+        registro.terminar().unwrap();
+        assert_eq!(registro.escritor.vaciados, 2);
+    }
+}
@@ -0,0 +1,231 @@
+// reloj.rs
+// Reloj simulado de paso fijo. Todos los hilos de la simulación avanzan al
+// mismo ritmo: en cada tick primero trabajan los controladores (semáforos y
+// generador) y después el motor de física, sincronizados con dos barreras.
+use std::sync::{Arc, Barrier, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Límites de la escala de tiempo
+pub const ESCALA_MINIMA: f32 = 0.25;
+pub const ESCALA_MAXIMA: f32 = 100.0;
+
+// Escalas por las que se pasa con + y - desde el teclado
+pub const ESCALAS: [f32; 9] = [0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 25.0, 50.0, 100.0];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Velocidad {
+    Escala(f32), // Veces el tiempo real
+    Maxima,      // Sin esperar entre pasos
+}
+
+struct EstadoReloj {
+    tick: u64,
+    velocidad: Velocidad,
+    limite: Option<u64>, // Tick en el que termina la simulación
+    terminado: bool,
+    pausado: bool,
+    pasos_pendientes: u32, // Ticks a avanzar estando en pausa
+}
+
+pub struct Reloj {
+    paso: f32, // Segundos simulados por tick
+    estado: Mutex<EstadoReloj>,
+    cambio: Condvar,
+    inicio_tick: Barrier,
+    fin_control: Barrier,
+}
+
+impl Reloj {
+    // `hilos`: cuántos hilos avanzan con el reloj (semáforos de cada cruce, generador y física)
+    pub fn new(paso: f32, hilos: usize) -> Self {
+        Self {
+            paso,
+            estado: Mutex::new(EstadoReloj {
+                tick: 0,
+                velocidad: Velocidad::Escala(1.0),
+                limite: None,
+                terminado: false,
+                pausado: false,
+                pasos_pendientes: 0,
+            }),
+            cambio: Condvar::new(),
+            // Los hilos de simulación más el hilo que marca el ritmo
+            inicio_tick: Barrier::new(hilos + 1),
+            fin_control: Barrier::new(hilos + 1),
+        }
+    }
+
+    pub fn paso(&self) -> f32 {
+        self.paso
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.estado.lock().unwrap().tick
+    }
+
+    // Segundos simulados transcurridos
+    pub fn tiempo(&self) -> f32 {
+        self.tiempo_de(self.tick())
+    }
+
+    pub fn tiempo_de(&self, tick: u64) -> f32 {
+        tick as f32 * self.paso
+    }
+
+    pub fn velocidad(&self) -> Velocidad {
+        self.estado.lock().unwrap().velocidad
+    }
+
+    pub fn set_velocidad(&self, velocidad: Velocidad) {
+        let velocidad = match velocidad {
+            Velocidad::Escala(f) => Velocidad::Escala(f.clamp(ESCALA_MINIMA, ESCALA_MAXIMA)),
+            Velocidad::Maxima => Velocidad::Maxima,
+        };
+        self.estado.lock().unwrap().velocidad = velocidad;
+    }
+
+    // Siguiente escala más rápida; después de la última va "lo más rápido posible"
+    pub fn acelerar(&self) {
+        let mut estado = self.estado.lock().unwrap();
+        estado.velocidad = match estado.velocidad {
+            Velocidad::Escala(actual) => match ESCALAS.iter().find(|&&e| e > actual) {
+                Some(&siguiente) => Velocidad::Escala(siguiente),
+                None => Velocidad::Maxima,
+            },
+            Velocidad::Maxima => Velocidad::Maxima,
+        };
+    }
+
+    pub fn desacelerar(&self) {
+        let mut estado = self.estado.lock().unwrap();
+        estado.velocidad = match estado.velocidad {
+            Velocidad::Escala(actual) => match ESCALAS.iter().rev().find(|&&e| e < actual) {
+                Some(&anterior) => Velocidad::Escala(anterior),
+                None => Velocidad::Escala(ESCALA_MINIMA),
+            },
+            Velocidad::Maxima => Velocidad::Escala(ESCALA_MAXIMA),
+        };
+    }
+
+    pub fn pausado(&self) -> bool {
+        self.estado.lock().unwrap().pausado
+    }
+
+    pub fn alternar_pausa(&self) {
+        let mut estado = self.estado.lock().unwrap();
+        estado.pausado = !estado.pausado;
+        estado.pasos_pendientes = 0;
+        self.cambio.notify_all();
+    }
+
+    // Avanza un solo tick de física; solo tiene efecto en pausa
+    pub fn avanzar_un_paso(&self) {
+        let mut estado = self.estado.lock().unwrap();
+        if estado.pausado {
+            estado.pasos_pendientes += 1;
+            self.cambio.notify_all();
+        }
+    }
+
+    // Espera mientras la simulación esté en pausa. Devuelve true si hubo que esperar.
+    fn esperar_si_pausado(&self) -> bool {
+        let mut estado = self.estado.lock().unwrap();
+        let mut espero = false;
+        while estado.pausado && estado.pasos_pendientes == 0 && !estado.terminado {
+            estado = self.cambio.wait(estado).unwrap();
+            espero = true;
+        }
+        if estado.pausado && estado.pasos_pendientes > 0 {
+            estado.pasos_pendientes -= 1;
+            espero = true;
+        }
+        espero
+    }
+
+    // Detener la simulación después de cierta cantidad de segundos simulados,
+    // contados desde el tick actual
+    pub fn set_duracion(&self, segundos: f32) {
+        let mut estado = self.estado.lock().unwrap();
+        estado.limite = Some(estado.tick + (segundos / self.paso).ceil() as u64);
+    }
+
+    // Para seguir desde una instantánea; antes de lanzar los hilos
+    pub fn set_tick(&self, tick: u64) {
+        self.estado.lock().unwrap().tick = tick;
+    }
+
+    // Llamado por cada hilo al inicio de su ciclo. Devuelve el tick a simular
+    // o None si la simulación terminó y el hilo debe salir.
+    pub fn comenzar_tick(&self) -> Option<u64> {
+        self.inicio_tick.wait();
+        let estado = self.estado.lock().unwrap();
+        if estado.terminado { None } else { Some(estado.tick) }
+    }
+
+    // Marca el fin de la fase de control; la física trabaja después de esto
+    pub fn terminar_control(&self) {
+        self.fin_control.wait();
+    }
+
+    // Bloquea hasta que la simulación llegue a su duración
+    pub fn esperar_fin(&self) {
+        let mut estado = self.estado.lock().unwrap();
+        while !estado.terminado {
+            estado = self.cambio.wait(estado).unwrap();
+        }
+    }
+}
+
+// Hilo que marca el ritmo de los ticks según la velocidad elegida
+pub fn iniciar_reloj(reloj: Arc<Reloj>) {
+    thread::spawn(move || {
+        // Instante y tick desde los que se mide el ritmo
+        let mut referencia = (Instant::now(), reloj.tick());
+        let mut velocidad_anterior = reloj.velocidad();
+
+        loop {
+            // En pausa los hilos quedan detenidos en la barrera de inicio
+            if reloj.esperar_si_pausado() {
+                referencia = (Instant::now(), reloj.tick());
+            }
+
+            let fin = {
+                let mut estado = reloj.estado.lock().unwrap();
+                estado.terminado = estado.limite.is_some_and(|limite| estado.tick >= limite);
+                estado.terminado
+            };
+
+            reloj.inicio_tick.wait();
+            if fin {
+                reloj.cambio.notify_all();
+                return;
+            }
+            reloj.fin_control.wait();
+
+            let (tick, velocidad) = {
+                let mut estado = reloj.estado.lock().unwrap();
+                estado.tick += 1;
+                (estado.tick, estado.velocidad)
+            };
+
+            if velocidad != velocidad_anterior {
+                referencia = (Instant::now(), tick);
+                velocidad_anterior = velocidad;
+            }
+
+            if let Velocidad::Escala(escala) = velocidad {
+                let simulado = (tick - referencia.1) as f32 * reloj.paso;
+                let objetivo = referencia.0 + Duration::from_secs_f32(simulado / escala);
+                let ahora = Instant::now();
+
+                if objetivo > ahora {
+                    thread::sleep(objetivo - ahora);
+                } else if ahora - objetivo > Duration::from_millis(250) {
+                    // Vamos muy atrasados: no intentar recuperar de golpe
+                    referencia = (ahora, tick);
+                }
+            }
+        }
+    });
+}
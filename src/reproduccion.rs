@@ -0,0 +1,181 @@
+// reproduccion.rs
+// Ventana que muestra una grabación en vez de la simulación en vivo. Se puede
+// pausar, cambiar la velocidad, ir hacia atrás y moverse por la corrida con el
+// teclado o arrastrando la barra de tiempo.
+use crate::vista;
+use simulacion_trafico::grabacion::{Cuadro, Grabacion};
+use simulacion_trafico::modelo::PasoPeatonal;
+use simulacion_trafico::red::Red;
+use simulacion_trafico::reloj::ESCALAS;
+
+use ggez::event::{self, MouseButton};
+use ggez::input::keyboard::{KeyCode, KeyInput};
+use ggez::{graphics, Context, GameResult};
+use std::time::Instant;
+
+const AYUDA: &str = "Espacio: pausa   ←/→: 1 s   ↑/↓: 10 s   ,/. : un cuadro   +/-: velocidad   R: reversa   barra: ir a";
+
+pub struct Reproduccion {
+    grabacion: Grabacion,
+    red: Red,
+    pasos: Vec<PasoPeatonal>,
+    tiempo: f32,     // s simulados que se están mostrando
+    escala: f32,     // Veces el tiempo real
+    reversa: bool,
+    pausado: bool,
+    arrastrando: bool, // Con el botón apretado sobre la barra de tiempo
+    fps_contador: usize,
+    ultima_medicion_fps: Instant,
+    fps_actual: usize,
+}
+
+impl Reproduccion {
+    pub fn new(grabacion: Grabacion) -> Self {
+        // La red y los pasos peatonales salen del escenario con que se grabó, igual
+        // que en EstadoCompartido::new
+        let red = Red::new(&grabacion.escenario.red);
+        let pasos = grabacion.cuadros[0].semaforos.iter()
+            .map(|s| PasoPeatonal::new(&red, s.enlace, &grabacion.escenario))
+            .collect();
+        let tiempo = grabacion.cuadros[0].tiempo;
+
+        Self {
+            grabacion,
+            red,
+            pasos,
+            tiempo,
+            escala: 1.0,
+            reversa: false,
+            pausado: false,
+            arrastrando: false,
+            fps_contador: 0,
+            ultima_medicion_fps: Instant::now(),
+            fps_actual: 0,
+        }
+    }
+
+    fn ir_a(&mut self, tiempo: f32) {
+        let inicio = self.grabacion.cuadros[0].tiempo;
+        self.tiempo = tiempo.clamp(inicio, self.grabacion.duracion());
+    }
+
+    // Salta `cuadros` cuadros desde el que se muestra, hacia adelante o hacia atrás
+    fn mover_cuadros(&mut self, cuadros: isize) {
+        let cuadros_grabados = &self.grabacion.cuadros;
+        let actual = cuadros_grabados.partition_point(|c| c.tiempo <= self.tiempo).saturating_sub(1);
+        let destino = actual.saturating_add_signed(cuadros).min(cuadros_grabados.len() - 1);
+        self.tiempo = cuadros_grabados[destino].tiempo;
+    }
+
+    // Lleva el tiempo al punto de la barra bajo el ratón
+    fn ir_a_barra(&mut self, ctx: &Context, x: f32) {
+        let barra = vista::barra_tiempo(ctx);
+        let fraccion = ((x - barra.x) / barra.w).clamp(0.0, 1.0);
+        let inicio = self.grabacion.cuadros[0].tiempo;
+        self.ir_a(inicio + fraccion * (self.grabacion.duracion() - inicio));
+    }
+
+    fn cuadro(&self) -> &Cuadro {
+        self.grabacion.cuadro_en(self.tiempo)
+    }
+}
+
+impl event::EventHandler<ggez::GameError> for Reproduccion {
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        let ahora = Instant::now();
+        self.fps_contador += 1;
+        if ahora.duration_since(self.ultima_medicion_fps).as_secs() >= 1 {
+            self.fps_actual = self.fps_contador;
+            self.fps_contador = 0;
+            self.ultima_medicion_fps = ahora;
+        }
+
+        if !self.pausado && !self.arrastrando {
+            let avance = ctx.time.delta().as_secs_f32() * self.escala;
+            self.ir_a(if self.reversa { self.tiempo - avance } else { self.tiempo + avance });
+        }
+
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        let mut canvas = graphics::Canvas::from_frame(ctx, graphics::Color::new(0.5, 0.7, 0.9, 1.0));
+
+        vista::inicializar_cache(ctx, self.grabacion.semilla, &self.red)?;
+        vista::dibujar_fondo(&mut canvas, ctx)?;
+        vista::dibujar_elementos_decorativos(&mut canvas, ctx)?;
+        vista::dibujar_carreteras(&mut canvas, ctx, &self.red, &self.pasos)?;
+
+        let cuadro = self.cuadro();
+        vista::dibujar_cuadro(&mut canvas, ctx, cuadro, &self.pasos)?;
+
+        let mut estado = vec!["repetición"];
+        if self.reversa {
+            estado.push("hacia atrás");
+        }
+        if self.pausado {
+            estado.push("PAUSA");
+        }
+        let velocidad = format!("x{} ({})", self.escala, estado.join(", "));
+        vista::dibujar_ui(&mut canvas, ctx, cuadro, self.fps_actual, &velocidad, AYUDA)?;
+
+        let inicio = self.grabacion.cuadros[0].tiempo;
+        vista::dibujar_linea_tiempo(&mut canvas, ctx, self.tiempo - inicio, self.grabacion.duracion() - inicio)?;
+
+        canvas.finish(ctx)?;
+        Ok(())
+    }
+
+    fn key_down_event(&mut self, ctx: &mut Context, input: KeyInput, _repetida: bool) -> GameResult {
+        match input.keycode {
+            Some(KeyCode::Space) => self.pausado = !self.pausado,
+            Some(KeyCode::Right) => self.ir_a(self.tiempo + 1.0),
+            Some(KeyCode::Left) => self.ir_a(self.tiempo - 1.0),
+            Some(KeyCode::Up) => self.ir_a(self.tiempo + 10.0),
+            Some(KeyCode::Down) => self.ir_a(self.tiempo - 10.0),
+            Some(KeyCode::Period) | Some(KeyCode::NumpadDecimal) => {
+                self.pausado = true;
+                self.mover_cuadros(1);
+            }
+            Some(KeyCode::Comma) => {
+                self.pausado = true;
+                self.mover_cuadros(-1);
+            }
+            Some(KeyCode::Home) => self.ir_a(f32::NEG_INFINITY),
+            Some(KeyCode::End) => self.ir_a(f32::INFINITY),
+            Some(KeyCode::Plus) | Some(KeyCode::Equals) | Some(KeyCode::NumpadAdd) => {
+                self.escala = ESCALAS.iter().copied().find(|&e| e > self.escala).unwrap_or(self.escala);
+            }
+            Some(KeyCode::Minus) | Some(KeyCode::NumpadSubtract) => {
+                self.escala = ESCALAS.iter().rev().copied().find(|&e| e < self.escala).unwrap_or(self.escala);
+            }
+            Some(KeyCode::R) => self.reversa = !self.reversa,
+            Some(KeyCode::Escape) => ctx.request_quit(),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn mouse_button_down_event(&mut self, ctx: &mut Context, boton: MouseButton, x: f32, y: f32) -> GameResult {
+        if boton == MouseButton::Left && vista::barra_tiempo(ctx).contains([x, y]) {
+            self.arrastrando = true;
+            self.ir_a_barra(ctx, x);
+        }
+        Ok(())
+    }
+
+    fn mouse_button_up_event(&mut self, _ctx: &mut Context, boton: MouseButton, _x: f32, _y: f32) -> GameResult {
+        if boton == MouseButton::Left {
+            self.arrastrando = false;
+        }
+        Ok(())
+    }
+
+    fn mouse_motion_event(&mut self, ctx: &mut Context, x: f32, _y: f32, _dx: f32, _dy: f32) -> GameResult {
+        if self.arrastrando {
+            self.ir_a_barra(ctx, x);
+        }
+        Ok(())
+    }
+}
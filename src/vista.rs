@@ -1,9 +1,21 @@
 // vista.rs
-use crate::modelo::*;
+use simulacion_trafico::grabacion::Cuadro;
+use simulacion_trafico::modelo::{self, *};
+use simulacion_trafico::red::Red;
+use simulacion_trafico::reloj::{Reloj, Velocidad};
 use ggez::{graphics, Context, GameResult};
 use std::sync::Mutex;
 use std::sync::Once;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+
+// Conversión de los tipos del modelo a los de ggez
+fn a_color(color: modelo::Color) -> graphics::Color {
+    graphics::Color::new(color.r, color.g, color.b, color.a)
+}
+
+fn a_rect(rect: modelo::Rect) -> graphics::Rect {
+    graphics::Rect::new(rect.x, rect.y, rect.w, rect.h)
+}
 
 // Estructuras para elementos decorativos
 struct Arbol {
@@ -22,10 +34,9 @@ struct Edificio {
 
 // Estructuras para el caché de meshes
 struct MeshCache {
-    lineas_h: Vec<graphics::Mesh>,
-    lineas_v: Vec<graphics::Mesh>,
+    lineas: Vec<graphics::Mesh>, // Trazos de las líneas centrales y de carril de todas las vías
     bases_semaforos: graphics::Mesh,
-    vehiculos: [graphics::Mesh; 3], // Carro, Camioneta, Camión
+    vehiculos: [graphics::Mesh; 5], // Carro, Camioneta, Camión, Ambulancia, Policía
     arboles: Vec<graphics::Mesh>,   // Arboles, redondos puntiagudos
     edificios: Vec<Edificio>,       // Lista de edificios
     nubes: Vec<graphics::Mesh>,     // Nubecitas
@@ -34,24 +45,36 @@ struct MeshCache {
 }
 
 impl MeshCache {
-    pub fn new(ctx: &mut Context) -> GameResult<Self> {
-        let mut lineas_h = Vec::with_capacity(30);
-        let mut lineas_v = Vec::with_capacity(30);
-
-        for i in 0..30 {
-            lineas_h.push(graphics::Mesh::new_rectangle(
-                ctx,
-                graphics::DrawMode::fill(),
-                graphics::Rect::new(i as f32 * 20.0, 325.0, 10.0, 4.0),
-                COLOR_LINEA_CENTRAL,
-            )?);
+    pub fn new(ctx: &mut Context, semilla: u64, red: &Red) -> GameResult<Self> {
+        let mut lineas = Vec::new();
+
+        // Un trazo cada 20 px a lo largo de cada vía: amarillo en el eje y blanco
+        // entre carriles del mismo sentido, que no se pinta dentro de los cruces
+        for via in red.vias() {
+            let zona = red.zona_via(via);
+            let horizontal = via.direccion.eje_horizontal();
+            let (largo, ancho) = if horizontal { (zona.w, zona.h) } else { (zona.h, zona.w) };
+            let mut separaciones = vec![(0.0, a_color(COLOR_LINEA_CENTRAL))];
+            for k in 1..via.carriles {
+                let corrida = k as f32 * ANCHO_CARRIL;
+                separaciones.push((-corrida, graphics::Color::WHITE));
+                separaciones.push((corrida, graphics::Color::WHITE));
+            }
 
-            lineas_v.push(graphics::Mesh::new_rectangle(
-                ctx,
-                graphics::DrawMode::fill(),
-                graphics::Rect::new(325.0, i as f32 * 20.0, 4.0, 10.0),
-                COLOR_LINEA_CENTRAL,
-            )?);
+            for (separacion, color) in separaciones {
+                for i in 0..(largo / 20.0).ceil() as usize {
+                    let corrido = i as f32 * 20.0;
+                    let trazo = if horizontal {
+                        graphics::Rect::new(zona.x + corrido, zona.y + ancho / 2.0 + separacion, 10.0, 4.0)
+                    } else {
+                        graphics::Rect::new(zona.x + ancho / 2.0 + separacion, zona.y + corrido, 4.0, 10.0)
+                    };
+                    if separacion != 0.0 && red.cruce_en([trazo.x, trazo.y]).is_some() {
+                        continue;
+                    }
+                    lineas.push(graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), trazo, color)?);
+                }
+            }
         }
 
         let bases_semaforos = graphics::Mesh::new_rectangle(
@@ -61,28 +84,22 @@ impl MeshCache {
             graphics::Color::new(0.3, 0.3, 0.3, 1.0),
         )?;
 
-        let vehiculos = [
-            // Automóvil
+        // Los vehículos se dibujan centrados en su posición para poder rotarlos
+        let malla_vehiculo = |tipo: TipoVehiculo| {
+            let [largo, ancho] = tipo.dimensiones();
             graphics::Mesh::new_rectangle(
                 ctx,
                 graphics::DrawMode::fill(),
-                graphics::Rect::new(0.0, 0.0, 30.0, 15.0),
+                graphics::Rect::new(-largo / 2.0, -ancho / 2.0, largo, ancho),
                 graphics::Color::WHITE, // El color se aplica en tiempo de dibujo
-            )?,
-            // Camioneta
-            graphics::Mesh::new_rectangle(
-                ctx,
-                graphics::DrawMode::fill(),
-                graphics::Rect::new(0.0, 0.0, 35.0, 18.0),
-                graphics::Color::WHITE,
-            )?,
-            // Camión
-            graphics::Mesh::new_rectangle(
-                ctx,
-                graphics::DrawMode::fill(),
-                graphics::Rect::new(0.0, 0.0, 45.0, 20.0),
-                graphics::Color::WHITE,
-            )?,
+            )
+        };
+        let vehiculos = [
+            malla_vehiculo(TipoVehiculo::Automovil)?,
+            malla_vehiculo(TipoVehiculo::Camioneta)?,
+            malla_vehiculo(TipoVehiculo::Camion)?,
+            malla_vehiculo(TipoVehiculo::Ambulancia)?,
+            malla_vehiculo(TipoVehiculo::Policia)?,
         ];
 
         // Crear meshes para árboles
@@ -130,8 +147,10 @@ impl MeshCache {
         )?;
         arboles.push(copa_redonda);
 
-        // Generar edificios
-        let mut rng = rand::rng();
+        // Generar edificios. Se usa un flujo propio de la misma semilla para que
+        // dibujar no altere la secuencia aleatoria de la simulación.
+        let mut rng = Aleatorio::seed_from_u64(semilla);
+        rng.set_stream(1);
         let mut edificios = Vec::new();
 
         // Cuadrante superior izquierdo
@@ -219,8 +238,7 @@ impl MeshCache {
         )?;
 
         Ok(Self {
-            lineas_h,
-            lineas_v,
+            lineas,
             bases_semaforos,
             vehiculos,
             arboles,
@@ -244,9 +262,9 @@ static MESH_CACHE_SINGLETON: MeshCacheSingleton = MeshCacheSingleton {
     init: Once::new(),
 };
 
-pub fn inicializar_cache(ctx: &mut Context) -> GameResult {
+pub fn inicializar_cache(ctx: &mut Context, semilla: u64, red: &Red) -> GameResult {
     MESH_CACHE_SINGLETON.init.call_once(|| {
-        let cache = MeshCache::new(ctx).unwrap(); // Manejo básico de errores
+        let cache = MeshCache::new(ctx, semilla, red).unwrap(); // Manejo básico de errores
         *MESH_CACHE_SINGLETON.cache.lock().unwrap() = Some(cache);
     });
     Ok(())
@@ -261,53 +279,15 @@ fn get_cache<'a>() -> Option<std::sync::MutexGuard<'a, Option<MeshCache>>> {
 
 // vista.rs
 pub fn dibujar_fondo(canvas: &mut graphics::Canvas, ctx: &mut Context) -> GameResult {
-    // Cielo
-    let cielo = graphics::Mesh::new_rectangle(
-        ctx,
-        graphics::DrawMode::fill(),
-        graphics::Rect::new(0.0, 0.0, 600.0, 600.0),
-        graphics::Color::new(0.5, 0.7, 0.9, 1.0),
-    )?;
-    canvas.draw(&cielo, graphics::DrawParam::default());
-
-    // Pasto estático (4 rectángulos grandes alrededor de las carreteras)
-    let color_pasto = graphics::Color::new(0.2, 0.5, 0.2, 1.0);
-
-    // Zona superior (sobre la carretera horizontal)
-    let pasto_superior = graphics::Mesh::new_rectangle(
-        ctx,
-        graphics::DrawMode::fill(),
-        graphics::Rect::new(0.0, 0.0, 600.0, 295.0),
-        color_pasto,
-    )?;
-    canvas.draw(&pasto_superior, graphics::DrawParam::default());
-
-    // Zona inferior (debajo de la carretera horizontal)
-    let pasto_inferior = graphics::Mesh::new_rectangle(
-        ctx,
-        graphics::DrawMode::fill(),
-        graphics::Rect::new(0.0, 355.0, 600.0, 245.0),
-        color_pasto,
-    )?;
-    canvas.draw(&pasto_inferior, graphics::DrawParam::default());
-
-    // Zona izquierda (entre carreteras)
-    let pasto_izquierdo = graphics::Mesh::new_rectangle(
-        ctx,
-        graphics::DrawMode::fill(),
-        graphics::Rect::new(0.0, 295.0, 295.0, 60.0),
-        color_pasto,
-    )?;
-    canvas.draw(&pasto_izquierdo, graphics::DrawParam::default());
-
-    // Zona derecha (entre carreteras)
-    let pasto_derecho = graphics::Mesh::new_rectangle(
+    // Pasto en toda la ventana; las carreteras se dibujan encima
+    let (ancho, alto) = ctx.gfx.drawable_size();
+    let pasto = graphics::Mesh::new_rectangle(
         ctx,
         graphics::DrawMode::fill(),
-        graphics::Rect::new(355.0, 295.0, 245.0, 60.0),
-        color_pasto,
+        graphics::Rect::new(0.0, 0.0, ancho, alto),
+        graphics::Color::new(0.2, 0.5, 0.2, 1.0),
     )?;
-    canvas.draw(&pasto_derecho, graphics::DrawParam::default());
+    canvas.draw(&pasto, graphics::DrawParam::default());
 
     // Dibujar nubes (código existente)
     if let Some(ref guard) = get_cache() {
@@ -439,78 +419,56 @@ pub fn dibujar_elementos_decorativos(canvas: &mut graphics::Canvas, ctx: &mut Co
     Ok(())
 }
 
-pub fn dibujar_carreteras(canvas: &mut graphics::Canvas, ctx: &mut Context) -> GameResult {
-    // Asfalto
-    canvas.draw(&graphics::Quad, graphics::DrawParam::new()
-        .dest_rect(VIA_HORIZONTAL)
-        .color(COLOR_ASFALTO));
-
-    canvas.draw(&graphics::Quad, graphics::DrawParam::new()
-        .dest_rect(VIA_VERTICAL)
-        .color(COLOR_ASFALTO));
+pub fn dibujar_carreteras(canvas: &mut graphics::Canvas, ctx: &mut Context, red: &Red, pasos: &[PasoPeatonal]) -> GameResult {
+    // Aceras a los dos lados de cada vía; el asfalto las tapa en los cruces
+    for via in red.vias() {
+        let zona = red.zona_via(via);
+        let acera = if via.direccion.eje_horizontal() {
+            graphics::Rect::new(zona.x, zona.y - ANCHO_ACERA, zona.w, zona.h + 2.0 * ANCHO_ACERA)
+        } else {
+            graphics::Rect::new(zona.x - ANCHO_ACERA, zona.y, zona.w + 2.0 * ANCHO_ACERA, zona.h)
+        };
+        let mesh = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            acera,
+            graphics::Color::new(0.8, 0.8, 0.8, 1.0),
+        )?;
+        canvas.draw(&mesh, graphics::DrawParam::default());
+    }
 
-    // Inicializar caché si es necesario
-    MESH_CACHE_SINGLETON.init.call_once(|| {
-        if let Ok(cache) = MeshCache::new(ctx) {
-            *MESH_CACHE_SINGLETON.cache.lock().unwrap() = Some(cache);
-        }
-    });
+    // Asfalto
+    for via in red.vias() {
+        canvas.draw(&graphics::Quad, graphics::DrawParam::new()
+            .dest_rect(a_rect(red.zona_via(via)))
+            .color(a_color(COLOR_ASFALTO)));
+    }
 
     // Dibujar líneas centrales desde el caché
     if let Some(ref guard) = get_cache() {
         if let Some(ref cache) = **guard {
             // Dibujar líneas usando el caché
-            for linea in &cache.lineas_h {
-                canvas.draw(linea, graphics::DrawParam::default());
-            }
-
-            for linea in &cache.lineas_v {
+            for linea in &cache.lineas {
                 canvas.draw(linea, graphics::DrawParam::default());
             }
         }
     }
 
-    // Añadir aceras en los bordes de las carreteras
-    let aceras = [
-        // Aceras horizontales (arriba y abajo de la vía)
-        graphics::Rect::new(0.0, 290.0, 600.0, 10.0),
-        graphics::Rect::new(0.0, 350.0, 600.0, 10.0),
-        // Aceras verticales (izquierda y derecha de la vía)
-        graphics::Rect::new(290.0, 0.0, 10.0, 600.0),
-        graphics::Rect::new(350.0, 0.0, 10.0, 600.0),
-    ];
-
-    for acera in &aceras {
-        let mesh = graphics::Mesh::new_rectangle(
-            ctx,
-            graphics::DrawMode::fill(),
-            *acera,
-            graphics::Color::new(0.8, 0.8, 0.8, 1.0),
-        )?;
-        canvas.draw(&mesh, graphics::DrawParam::default());
-    }
-
-    // Añadir paso de peatones
-    let posiciones_paso = [
-        // Paso horizontal (a la izquierda de la intersección)
-        ([270.0, 310.0], false),
-        // Paso vertical (debajo de la intersección)
-        ([310.0, 350.0], true),
-    ];
-
-    for ([x, y], es_vertical) in &posiciones_paso {
-        for i in 0..5 {
-            let offset = i as f32 * 8.0;
-            let paso = if *es_vertical {
-                graphics::Rect::new(*x + offset, *y, 4.0, 20.0)
-            } else {
-                graphics::Rect::new(*x, *y + offset, 20.0, 4.0)
-            };
+    // Pasos de peatones: franjas paralelas a la vía, solo sobre la calzada
+    for paso in pasos {
+        let z = paso.zona;
+        let cantidad = (2.0 * paso.media_via / 8.0) as usize;
+        let franjas = if paso.acceso.eje_horizontal() {
+            (0..cantidad).map(|i| graphics::Rect::new(z.x, z.y + ANCHO_ACERA + 2.0 + i as f32 * 8.0, z.w, 4.0)).collect::<Vec<_>>()
+        } else {
+            (0..cantidad).map(|i| graphics::Rect::new(z.x + ANCHO_ACERA + 2.0 + i as f32 * 8.0, z.y, 4.0, z.h)).collect::<Vec<_>>()
+        };
 
+        for franja in franjas {
             let mesh = graphics::Mesh::new_rectangle(
                 ctx,
                 graphics::DrawMode::fill(),
-                paso,
+                franja,
                 graphics::Color::WHITE,
             )?;
             canvas.draw(&mesh, graphics::DrawParam::default());
@@ -570,43 +528,98 @@ pub fn dibujar_semaforo(canvas: &mut graphics::Canvas, ctx: &mut Context, semafo
     Ok(())
 }
 
+// Semáforo peatonal en cada extremo del paso, sobre la acera del lado de la intersección
+pub fn dibujar_semaforo_peatonal(canvas: &mut graphics::Canvas, ctx: &mut Context, paso: &PasoPeatonal, estado: EstadoPeatonal) -> GameResult {
+    let parpadeo = ((ctx.time.time_since_start().as_secs_f32() * 3.0) as u32).is_multiple_of(2);
+    let color = match estado {
+        EstadoPeatonal::Pasar => graphics::Color::new(0.9, 1.0, 0.9, 1.0),
+        EstadoPeatonal::Despejar if parpadeo => graphics::Color::new(0.3, 0.3, 0.3, 1.0),
+        EstadoPeatonal::Despejar | EstadoPeatonal::NoPasar => graphics::Color::new(1.0, 0.5, 0.0, 1.0),
+    };
+
+    let [x, y] = paso.acceso.vector();
+    let corrimiento = ANCHO_PASO / 2.0 + 4.0;
+    for [px, py] in paso.extremos() {
+        let luz = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(-3.0, -3.0, 6.0, 6.0),
+            color,
+        )?;
+        canvas.draw(&luz, graphics::DrawParam::new().dest([px + x * corrimiento, py + y * corrimiento]));
+    }
+
+    Ok(())
+}
+
+pub fn dibujar_peaton(canvas: &mut graphics::Canvas, ctx: &mut Context, peaton: &Peaton) -> GameResult {
+    let cuerpo = graphics::Mesh::new_circle(
+        ctx,
+        graphics::DrawMode::fill(),
+        peaton.posicion,
+        RADIO_PEATON,
+        0.1,
+        a_color(peaton.color),
+    )?;
+    canvas.draw(&cuerpo, graphics::DrawParam::default());
+
+    Ok(())
+}
+
+// Marca en el punto de contacto de un choque, que se desvanece en unos segundos
+pub fn dibujar_choque(canvas: &mut graphics::Canvas, ctx: &mut Context, choque: &Choque, ahora: f32) -> GameResult {
+    let transcurrido = ahora - choque.tiempo;
+    if !(0.0..DURACION_MARCA_CHOQUE).contains(&transcurrido) {
+        return Ok(());
+    }
+    let alfa = 1.0 - transcurrido / DURACION_MARCA_CHOQUE;
+    let marca = graphics::Mesh::new_circle(
+        ctx,
+        graphics::DrawMode::stroke(2.0),
+        choque.posicion,
+        6.0 + 4.0 * transcurrido,
+        0.1,
+        graphics::Color::new(1.0, 0.5, 0.0, alfa),
+    )?;
+    canvas.draw(&marca, graphics::DrawParam::default());
+
+    Ok(())
+}
+
 pub fn dibujar_vehiculo(canvas: &mut graphics::Canvas, ctx: &mut Context, carro: &Carro) -> GameResult {
     let mesh_idx = match carro.tipo {
         TipoVehiculo::Automovil => 0,
         TipoVehiculo::Camioneta => 1,
         TipoVehiculo::Camion => 2,
+        TipoVehiculo::Ambulancia => 3,
+        TipoVehiculo::Policia => 4,
     };
 
-    let rotacion = match carro.direccion {
-        "este" => 0.0,
-        "norte" => std::f32::consts::FRAC_PI_2,
-        _ => 0.0,
-    };
+    let rotacion = carro.angulo;
+    let [largo, ancho] = carro.tipo.dimensiones();
 
-    if carro.loco {
-        match carro.tipo {
-            TipoVehiculo::Automovil => 0,
-            TipoVehiculo::Camioneta => 1,
-            TipoVehiculo::Camion => 2,
-        };
+    // Los orillados se dibujan corridos hacia su derecha, sin cambiar su posición en el carril
+    let mut posicion = carro.posicion;
+    if carro.orillado {
+        posicion[0] -= rotacion.sin() * DESPLAZAMIENTO_ORILLA;
+        posicion[1] += rotacion.cos() * DESPLAZAMIENTO_ORILLA;
+    }
 
-        // Dimensiones según tipo de vehículo
-        let (ancho, alto) = match carro.tipo {
-            TipoVehiculo::Automovil => (34.0, 19.0),
-            TipoVehiculo::Camioneta => (39.0, 22.0),
-            TipoVehiculo::Camion => (49.0, 24.0),
-        };
+    if let Some(siniestro) = carro.siniestro {
+        return dibujar_chocado(canvas, ctx, carro, siniestro, posicion);
+    }
 
+    if carro.loco {
         // Halo rojo para indicar vehículo loco
         let halo = graphics::Mesh::new_rectangle(
             ctx,
             graphics::DrawMode::stroke(2.0),
-            graphics::Rect::new(-2.0, -2.0, ancho, alto),
+            graphics::Rect::new(-largo / 2.0 - 2.0, -ancho / 2.0 - 2.0, largo + 4.0, ancho + 4.0),
             graphics::Color::RED,
         )?;
 
         canvas.draw(&halo, graphics::DrawParam::new()
-            .dest(carro.posicion)
+            .dest(posicion)
             .rotation(rotacion));
     }
 
@@ -614,131 +627,261 @@ pub fn dibujar_vehiculo(canvas: &mut graphics::Canvas, ctx: &mut Context, carro:
     if let Some(ref guard) = get_cache() {
         if let Some(ref cache) = **guard {
             canvas.draw(&cache.vehiculos[mesh_idx], graphics::DrawParam::new()
-                .dest(carro.posicion)
+                .dest(posicion)
                 .rotation(rotacion)
-                .color(carro.color));
-
-            // Añadir detalles al vehículo
-            match carro.tipo {
-                TipoVehiculo::Automovil => {
-                    // Ventanas para automóvil
-                    let pos_x = carro.posicion[0];
-                    let pos_y = carro.posicion[1];
-
-                    // Ajustar posición según rotación
-                    let (ventana_x, ventana_y, ventana_ancho, ventana_alto) = if carro.direccion == "este" {
-                        (pos_x + 10.0, pos_y + 3.0, 12.0, 9.0)
-                    } else {
-                        (pos_x + 3.0, pos_y + 8.0, 9.0, 12.0)
-                    };
-
-                    let ventana = graphics::Mesh::new_rectangle(
-                        ctx,
-                        graphics::DrawMode::fill(),
-                        graphics::Rect::new(0.0, 0.0, ventana_ancho, ventana_alto),
-                        graphics::Color::new(0.7, 0.8, 0.9, 1.0),
-                    )?;
+                .color(a_color(carro.color)));
+
+            // Añadir detalles al vehículo: ventanas según el tipo, medidas desde el centro
+            let (desplazamiento, ventana_ancho, ventana_alto) = match carro.tipo {
+                TipoVehiculo::Automovil => ([-5.0, -4.5], 12.0, 9.0),
+                TipoVehiculo::Camioneta => ([2.5, -6.0], 10.0, 12.0),
+                TipoVehiculo::Camion => ([5.5, -6.0], 12.0, 12.0),
+                TipoVehiculo::Ambulancia => ([8.0, -6.0], 10.0, 12.0),
+                TipoVehiculo::Policia => ([-4.0, -4.5], 10.0, 9.0),
+            };
 
-                    canvas.draw(&ventana, graphics::DrawParam::new()
-                        .dest([ventana_x, ventana_y])
-                        .rotation(rotacion));
-                },
-                TipoVehiculo::Camioneta | TipoVehiculo::Camion => {
-                    // Ventanas para camionetas y camiones
-                    let pos_x = carro.posicion[0];
-                    let pos_y = carro.posicion[1];
-
-                    // Ajustar posición según rotación
-                    let (ventana_x, ventana_y, ventana_ancho, ventana_alto) = if carro.direccion == "este" {
-                        let ancho = if carro.tipo == TipoVehiculo::Camioneta { 10.0 } else { 12.0 };
-                        (pos_x + 5.0, pos_y + 3.0, ancho, 12.0)
-                    } else {
-                        let ancho = if carro.tipo == TipoVehiculo::Camioneta { 12.0 } else { 12.0 };
-                        (pos_x + 3.0, pos_y + 5.0, ancho, 10.0)
-                    };
+            let ventana = graphics::Mesh::new_rectangle(
+                ctx,
+                graphics::DrawMode::fill(),
+                graphics::Rect::new(desplazamiento[0], desplazamiento[1], ventana_ancho, ventana_alto),
+                graphics::Color::new(0.7, 0.8, 0.9, 1.0),
+            )?;
 
-                    let ventana = graphics::Mesh::new_rectangle(
+            // La ventana está en coordenadas del vehículo, así que se rota con él
+            canvas.draw(&ventana, graphics::DrawParam::new()
+                .dest(posicion)
+                .rotation(rotacion));
+
+            if carro.tipo.es_emergencia() {
+                // Sirena que alterna azul y rojo dos veces por segundo
+                let encendida = ((ctx.time.time_since_start().as_secs_f32() * 4.0) as u32).is_multiple_of(2);
+                let (izquierda, derecha) = if encendida {
+                    (graphics::Color::BLUE, graphics::Color::RED)
+                } else {
+                    (graphics::Color::RED, graphics::Color::BLUE)
+                };
+                for (y, color) in [(-4.0, izquierda), (0.0, derecha)] {
+                    let luz = graphics::Mesh::new_rectangle(
                         ctx,
                         graphics::DrawMode::fill(),
-                        graphics::Rect::new(0.0, 0.0, ventana_ancho, ventana_alto),
-                        graphics::Color::new(0.7, 0.8, 0.9, 1.0),
+                        graphics::Rect::new(-2.0, y, 4.0, 4.0),
+                        color,
                     )?;
-
-                    canvas.draw(&ventana, graphics::DrawParam::new()
-                        .dest([ventana_x, ventana_y])
+                    canvas.draw(&luz, graphics::DrawParam::new()
+                        .dest(posicion)
                         .rotation(rotacion));
-                },
+                }
             }
 
             return Ok(());
         }
     }
 
-
-    let (ancho, alto) = match carro.tipo {
-        TipoVehiculo::Automovil => (30.0, 15.0),
-        TipoVehiculo::Camioneta => (35.0, 18.0),
-        TipoVehiculo::Camion => (45.0, 20.0),
-    };
-
     let cuerpo = graphics::Mesh::new_rectangle(
         ctx,
         graphics::DrawMode::fill(),
-        graphics::Rect::new(0.0, 0.0, ancho, alto),
-        carro.color,
+        graphics::Rect::new(-largo / 2.0, -ancho / 2.0, largo, ancho),
+        a_color(carro.color),
     )?;
 
     canvas.draw(&cuerpo, graphics::DrawParam::new()
-        .dest(carro.posicion)
+        .dest(posicion)
         .rotation(rotacion));
 
     Ok(())
 }
 
+// Vehículo chocado: más oscuro, con la carrocería abollada y humo según la gravedad
+fn dibujar_chocado(canvas: &mut graphics::Canvas, ctx: &mut Context, carro: &Carro, siniestro: Siniestro, posicion: [f32; 2]) -> GameResult {
+    let [largo, ancho] = carro.tipo.dimensiones();
+    let oscuro = graphics::Color::new(carro.color.r * 0.5, carro.color.g * 0.5, carro.color.b * 0.5, 1.0);
+    let cuerpo = graphics::Mesh::new_rectangle(
+        ctx,
+        graphics::DrawMode::fill(),
+        graphics::Rect::new(-largo / 2.0, -ancho / 2.0, largo, ancho),
+        oscuro,
+    )?;
+    canvas.draw(&cuerpo, graphics::DrawParam::new()
+        .dest(posicion)
+        .rotation(carro.angulo));
+
+    // Abolladuras: líneas quebradas sobre la carrocería
+    let abolladura = graphics::Mesh::new_line(
+        ctx,
+        &[
+            [-largo / 2.0, -ancho / 4.0],
+            [-largo / 6.0, ancho / 4.0],
+            [largo / 6.0, -ancho / 4.0],
+            [largo / 2.0, ancho / 4.0],
+        ],
+        2.0,
+        graphics::Color::BLACK,
+    )?;
+    canvas.draw(&abolladura, graphics::DrawParam::new()
+        .dest(posicion)
+        .rotation(carro.angulo));
+
+    // Humo que crece con la gravedad
+    let radio = match siniestro.gravedad {
+        Gravedad::Leve => 0.0,
+        Gravedad::Moderado => 6.0,
+        Gravedad::Grave => 10.0,
+    };
+    if radio > 0.0 {
+        let humo = graphics::Mesh::new_circle(
+            ctx,
+            graphics::DrawMode::fill(),
+            posicion,
+            radio,
+            0.1,
+            graphics::Color::new(0.3, 0.3, 0.3, 0.6),
+        )?;
+        canvas.draw(&humo, graphics::DrawParam::default());
+    }
+
+    Ok(())
+}
+
+// Semáforos, peatones, vehículos y marcas de choque de un cuadro, en vivo o grabado
+pub fn dibujar_cuadro(canvas: &mut graphics::Canvas, ctx: &mut Context, cuadro: &Cuadro, pasos: &[PasoPeatonal]) -> GameResult {
+    for semaforo in &cuadro.semaforos {
+        dibujar_semaforo(canvas, ctx, semaforo)?;
+    }
+
+    for (paso, &estado) in pasos.iter().zip(&cuadro.semaforos_peatonales) {
+        dibujar_semaforo_peatonal(canvas, ctx, paso, estado)?;
+    }
+
+    for peaton in &cuadro.peatones {
+        dibujar_peaton(canvas, ctx, peaton)?;
+    }
+
+    for carro in &cuadro.carros {
+        dibujar_vehiculo(canvas, ctx, carro)?;
+    }
+
+    for choque in &cuadro.choques {
+        dibujar_choque(canvas, ctx, choque, cuadro.tiempo)?;
+    }
+
+    Ok(())
+}
+
+// Escala del reloj para el panel, con la pausa
+pub fn texto_velocidad(reloj: &Reloj) -> String {
+    let velocidad = match reloj.velocidad() {
+        Velocidad::Escala(escala) => format!("x{}", escala),
+        Velocidad::Maxima => "máxima".to_string(),
+    };
+    let estado_reloj = if reloj.pausado() { " (PAUSA)" } else { "" };
+    format!("{}{}", velocidad, estado_reloj)
+}
+
 pub fn dibujar_ui(
     canvas: &mut graphics::Canvas,
-    ctx: &mut Context,       // Añadir este parámetro
-    num_vehiculos: usize,
-    direccion_activa: &str,
+    ctx: &mut Context,
+    cuadro: &Cuadro,
     fps: usize,
-    num_accidentes: usize
+    velocidad: &str,
+    ayuda: &str, // Teclas, al pie de la ventana
 ) -> GameResult {
-    // Panel para UI
+    let num_accidentes = cuadro.accidentes;
+
+    // Una fase por cruce semaforizado; los nodos sin semáforos quedan vacíos
+    let fases = cuadro.direccion_activa.iter()
+        .filter(|direcciones| !direcciones.is_empty())
+        .map(|direcciones| direcciones.iter().map(|d| d.nombre()).collect::<Vec<_>>().join("/"))
+        .collect::<Vec<_>>()
+        .join(" | ");
+
+    let texto = graphics::Text::new(format!(
+        "Vehículos: {}\nDirección activa: {}\nFPS: {}\nAccidentes: {}\nAtropellos: {}\nTiempo: {:.1} s\nVelocidad: {}",  // <-- Añadir accidentes
+        cuadro.carros.len(),
+        fases,
+        fps,
+        num_accidentes,
+        cuadro.atropellos,
+        cuadro.tiempo,
+        velocidad
+    ));
+
+    // Panel para UI, tan ancho como el texto (con varios cruces la fase es larga)
+    let medida = texto.measure(ctx)?;
     let panel = graphics::Mesh::new_rectangle(
         ctx,
         graphics::DrawMode::fill(),
-        graphics::Rect::new(5.0, 5.0, 200.0, 100.0),
+        graphics::Rect::new(5.0, 5.0, (medida.x + 20.0).max(200.0), 160.0),
         graphics::Color::new(0.0, 0.0, 0.0, 0.6),
     )?;
     canvas.draw(&panel, graphics::DrawParam::default());
 
-    let texto = graphics::Text::new(format!(
-        "Vehículos: {}\nDirección activa: {}\nFPS: {}\nAccidentes: {}",  // <-- Añadir accidentes
-        num_vehiculos,
-        direccion_activa,
-        fps,
-        num_accidentes
-    ));
-
     canvas.draw(&texto, graphics::DrawParam::new()
         .dest([15.0, 15.0])
         .color(graphics::Color::WHITE));
 
+    let (ancho, alto) = ctx.gfx.drawable_size();
     if num_accidentes > 0 {
         let panel_alerta = graphics::Mesh::new_rectangle(
             ctx,
             graphics::DrawMode::fill(),
-            graphics::Rect::new(400.0, 5.0, 195.0, 30.0),
+            graphics::Rect::new(ancho - 200.0, 5.0, 195.0, 30.0),
             graphics::Color::new(0.8, 0.0, 0.0, 0.8),  // Rojo semi-transparente
         )?;
         canvas.draw(&panel_alerta, graphics::DrawParam::default());
 
         let texto_alerta = graphics::Text::new("¡Cuidado! Conductores locos");
         canvas.draw(&texto_alerta, graphics::DrawParam::new()
-            .dest([410.0, 10.0])
+            .dest([ancho - 190.0, 10.0])
             .color(graphics::Color::WHITE));
     }
 
+    // Ayuda de controles
+    let ayuda = graphics::Text::new(ayuda);
+    canvas.draw(&ayuda, graphics::DrawParam::new()
+        .dest([10.0, alto - 20.0])
+        .color(graphics::Color::WHITE));
+
+
+    Ok(())
+}
 
+// Panel con el informe de estadísticas, debajo del panel principal
+pub fn dibujar_estadisticas(canvas: &mut graphics::Canvas, ctx: &mut Context, informe: &str) -> GameResult {
+    let texto = graphics::Text::new(informe);
+    let medida = texto.measure(ctx)?;
+    let panel = graphics::Mesh::new_rectangle(
+        ctx,
+        graphics::DrawMode::fill(),
+        graphics::Rect::new(5.0, 175.0, medida.x + 20.0, medida.y + 20.0),
+        graphics::Color::new(0.0, 0.0, 0.0, 0.6),
+    )?;
+    canvas.draw(&panel, graphics::DrawParam::default());
+    canvas.draw(&texto, graphics::DrawParam::new()
+        .dest([15.0, 185.0])
+        .color(graphics::Color::WHITE));
+    Ok(())
+}
+// Barra de tiempo de la reproducción, sobre la línea de ayuda
+pub fn barra_tiempo(ctx: &Context) -> graphics::Rect {
+    let (ancho, alto) = ctx.gfx.drawable_size();
+    graphics::Rect::new(10.0, alto - 45.0, ancho - 20.0, 14.0)
+}
+
+pub fn dibujar_linea_tiempo(canvas: &mut graphics::Canvas, ctx: &mut Context, transcurrido: f32, total: f32) -> GameResult {
+    let barra = barra_tiempo(ctx);
+    let fraccion = if total > 0.0 { (transcurrido / total).clamp(0.0, 1.0) } else { 1.0 };
+
+    let fondo = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), barra, graphics::Color::new(0.0, 0.0, 0.0, 0.6))?;
+    canvas.draw(&fondo, graphics::DrawParam::default());
+    if fraccion > 0.0 {
+        let avance = graphics::Rect::new(barra.x, barra.y, barra.w * fraccion, barra.h);
+        let lleno = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), avance, graphics::Color::new(1.0, 0.6, 0.1, 0.9))?;
+        canvas.draw(&lleno, graphics::DrawParam::default());
+    }
+
+    let texto = graphics::Text::new(format!("{:.1} / {:.1} s", transcurrido, total));
+    canvas.draw(&texto, graphics::DrawParam::new()
+        .dest([barra.x + 5.0, barra.y - 20.0])
+        .color(graphics::Color::WHITE));
     Ok(())
 }
\ No newline at end of file